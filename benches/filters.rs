@@ -283,3 +283,252 @@ fn filters_1_bits_filter_5(b: &mut Bencher) {
 
     b.iter(|| png.raw.filter_image(RowFilter::MinSum, false));
 }
+
+#[bench]
+fn unfilters_16_bits_filter_1(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_16_should_be_rgb_16.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = png.raw.filter_image(RowFilter::Sub, false);
+    let compressed = deflate(&filtered, 1, None).unwrap();
+
+    b.iter(|| PngImage::new(png.raw.ihdr.clone(), &compressed).unwrap());
+}
+
+#[bench]
+fn unfilters_8_bits_filter_1(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = png.raw.filter_image(RowFilter::Sub, false);
+    let compressed = deflate(&filtered, 1, None).unwrap();
+
+    b.iter(|| PngImage::new(png.raw.ihdr.clone(), &compressed).unwrap());
+}
+
+#[bench]
+fn unfilters_16_bits_filter_2(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_16_should_be_rgb_16.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = png.raw.filter_image(RowFilter::Up, false);
+    let compressed = deflate(&filtered, 1, None).unwrap();
+
+    b.iter(|| PngImage::new(png.raw.ihdr.clone(), &compressed).unwrap());
+}
+
+#[bench]
+fn unfilters_8_bits_filter_2(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = png.raw.filter_image(RowFilter::Up, false);
+    let compressed = deflate(&filtered, 1, None).unwrap();
+
+    b.iter(|| PngImage::new(png.raw.ihdr.clone(), &compressed).unwrap());
+}
+
+#[bench]
+fn unfilters_16_bits_filter_3(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_16_should_be_rgb_16.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = png.raw.filter_image(RowFilter::Average, false);
+    let compressed = deflate(&filtered, 1, None).unwrap();
+
+    b.iter(|| PngImage::new(png.raw.ihdr.clone(), &compressed).unwrap());
+}
+
+#[bench]
+fn unfilters_8_bits_filter_3(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = png.raw.filter_image(RowFilter::Average, false);
+    let compressed = deflate(&filtered, 1, None).unwrap();
+
+    b.iter(|| PngImage::new(png.raw.ihdr.clone(), &compressed).unwrap());
+}
+
+#[bench]
+fn unfilters_16_bits_filter_4(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_16_should_be_rgb_16.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = png.raw.filter_image(RowFilter::Paeth, false);
+    let compressed = deflate(&filtered, 1, None).unwrap();
+
+    b.iter(|| PngImage::new(png.raw.ihdr.clone(), &compressed).unwrap());
+}
+
+#[bench]
+fn unfilters_8_bits_filter_4(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = png.raw.filter_image(RowFilter::Paeth, false);
+    let compressed = deflate(&filtered, 1, None).unwrap();
+
+    b.iter(|| PngImage::new(png.raw.ihdr.clone(), &compressed).unwrap());
+}
+
+/// Filter each line of a non-interlaced image, with or without SIMD
+fn filter_lines(png: &PngImage, filter: RowFilter, scalar: bool) -> Vec<u8> {
+    let bpp = png.ihdr.bpp().div_ceil(8);
+    let line_len = png.data.len() / png.ihdr.height as usize;
+    let mut filtered = Vec::with_capacity(png.data.len() + png.ihdr.height as usize);
+    let mut prev_line = vec![0; line_len];
+    let mut line_data = vec![0; line_len];
+    let mut buf = Vec::new();
+    for line in png.data.chunks(line_len) {
+        line_data.copy_from_slice(line);
+        filter_line(filter, bpp, &mut line_data, &prev_line, &mut buf, scalar);
+        filtered.extend_from_slice(&buf);
+        prev_line.copy_from_slice(line);
+    }
+    filtered
+}
+
+/// Unfilter each line of a non-interlaced image, with or without SIMD
+fn unfilter_lines(png: &PngImage, filtered: &[u8], scalar: bool) -> Vec<u8> {
+    let bpp = png.ihdr.bpp().div_ceil(8);
+    let line_len = png.data.len() / png.ihdr.height as usize;
+    let mut data = Vec::with_capacity(png.data.len());
+    let mut prev_line = vec![0; line_len];
+    let mut buf = Vec::new();
+    for line in filtered.chunks(line_len + 1) {
+        let filter = RowFilter::try_from(line[0]).unwrap();
+        unfilter_line(filter, bpp, &line[1..], &prev_line, &mut buf, scalar).unwrap();
+        data.extend_from_slice(&buf);
+        prev_line.copy_from_slice(&buf);
+    }
+    data
+}
+
+#[bench]
+fn filter_lines_8_bits_filter_1_simd(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+
+    b.iter(|| filter_lines(&png.raw, RowFilter::Sub, false));
+}
+
+#[bench]
+fn filter_lines_8_bits_filter_1_scalar(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+
+    b.iter(|| filter_lines(&png.raw, RowFilter::Sub, true));
+}
+
+#[bench]
+fn unfilter_lines_8_bits_filter_1_simd(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = filter_lines(&png.raw, RowFilter::Sub, true);
+
+    b.iter(|| unfilter_lines(&png.raw, &filtered, false));
+}
+
+#[bench]
+fn unfilter_lines_8_bits_filter_1_scalar(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = filter_lines(&png.raw, RowFilter::Sub, true);
+
+    b.iter(|| unfilter_lines(&png.raw, &filtered, true));
+}
+
+#[bench]
+fn filter_lines_8_bits_filter_2_simd(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+
+    b.iter(|| filter_lines(&png.raw, RowFilter::Up, false));
+}
+
+#[bench]
+fn filter_lines_8_bits_filter_2_scalar(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+
+    b.iter(|| filter_lines(&png.raw, RowFilter::Up, true));
+}
+
+#[bench]
+fn unfilter_lines_8_bits_filter_2_simd(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = filter_lines(&png.raw, RowFilter::Up, true);
+
+    b.iter(|| unfilter_lines(&png.raw, &filtered, false));
+}
+
+#[bench]
+fn unfilter_lines_8_bits_filter_2_scalar(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = filter_lines(&png.raw, RowFilter::Up, true);
+
+    b.iter(|| unfilter_lines(&png.raw, &filtered, true));
+}
+
+#[bench]
+fn filter_lines_8_bits_filter_3_simd(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+
+    b.iter(|| filter_lines(&png.raw, RowFilter::Average, false));
+}
+
+#[bench]
+fn filter_lines_8_bits_filter_3_scalar(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+
+    b.iter(|| filter_lines(&png.raw, RowFilter::Average, true));
+}
+
+#[bench]
+fn unfilter_lines_8_bits_filter_3_simd(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = filter_lines(&png.raw, RowFilter::Average, true);
+
+    b.iter(|| unfilter_lines(&png.raw, &filtered, false));
+}
+
+#[bench]
+fn unfilter_lines_8_bits_filter_3_scalar(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = filter_lines(&png.raw, RowFilter::Average, true);
+
+    b.iter(|| unfilter_lines(&png.raw, &filtered, true));
+}
+
+#[bench]
+fn filter_lines_8_bits_filter_4_simd(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+
+    b.iter(|| filter_lines(&png.raw, RowFilter::Paeth, false));
+}
+
+#[bench]
+fn filter_lines_8_bits_filter_4_scalar(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+
+    b.iter(|| filter_lines(&png.raw, RowFilter::Paeth, true));
+}
+
+#[bench]
+fn unfilter_lines_8_bits_filter_4_simd(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = filter_lines(&png.raw, RowFilter::Paeth, true);
+
+    b.iter(|| unfilter_lines(&png.raw, &filtered, false));
+}
+
+#[bench]
+fn unfilter_lines_8_bits_filter_4_scalar(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+    let filtered = filter_lines(&png.raw, RowFilter::Paeth, true);
+
+    b.iter(|| unfilter_lines(&png.raw, &filtered, true));
+}
@@ -272,6 +272,36 @@ fn reductions_palette_sort_battiato(b: &mut Bencher) {
     b.iter(|| palette::sorted_palette_battiato(&png.raw));
 }
 
+#[bench]
+fn reductions_palette_sort_nearest_neighbor(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from(
+        "tests/files/palette_8_should_be_palette_8.png",
+    ));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+
+    b.iter(|| palette::sorted_palette_nearest_neighbor(&png.raw));
+}
+
+#[bench]
+fn reductions_palette_sort_annealing(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from(
+        "tests/files/palette_8_should_be_palette_8.png",
+    ));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+
+    b.iter(|| palette::sorted_palette_annealing(&png.raw));
+}
+
+#[bench]
+fn reductions_palette_sort_frequency(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from(
+        "tests/files/palette_8_should_be_palette_8.png",
+    ));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+
+    b.iter(|| palette::sorted_palette_frequency(&png.raw));
+}
+
 #[bench]
 fn reductions_alpha(b: &mut Bencher) {
     let input = test::black_box(PathBuf::from("tests/files/rgba_8_reduce_alpha.png"));
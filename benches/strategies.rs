@@ -47,3 +47,19 @@ fn filters_brute(b: &mut Bencher) {
 
     b.iter(|| png.raw.filter_image(RowFilter::Brute, false));
 }
+
+#[bench]
+fn filters_brute_model(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+
+    b.iter(|| png.raw.filter_image(RowFilter::BruteModel, false));
+}
+
+#[bench]
+fn filters_genetic(b: &mut Bencher) {
+    let input = test::black_box(PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png"));
+    let png = PngData::new(&input, &Options::default()).unwrap();
+
+    b.iter(|| png.raw.filter_image(RowFilter::Genetic, false));
+}
@@ -1,11 +1,78 @@
 use std::io::Write;
 
+use log::{debug, warn};
+use rayon::prelude::*;
+use rgb::RGBA16;
+
+#[cfg(not(feature = "parallel"))]
+use crate::rayon;
 use crate::{
+    colors::ColorType,
     error::PngError,
-    headers::{read_be_u16, read_be_u32},
-    PngResult,
+    headers::{read_be_u16, read_be_u32, Chunk, IhdrData},
+    interlace::Interlacing,
+    png::{PngData, PngImage},
+    reduction::{
+        bit_depth::expanded_bit_depth_to_8,
+        convert::{image_from_rgba16, rgba16_pixels},
+    },
+    Deadline, Deflaters, PngResult, RowFilter,
 };
 
+/// Leave the frame region as it is before rendering the next frame
+const DISPOSE_OP_NONE: u8 = 0;
+/// Clear the frame region to transparent black before rendering the next frame
+const DISPOSE_OP_BACKGROUND: u8 = 1;
+/// Revert the frame region to its previous contents before rendering the next frame
+const DISPOSE_OP_PREVIOUS: u8 = 2;
+/// Replace the frame region with the frame
+const BLEND_OP_SOURCE: u8 = 0;
+/// Composite the frame onto the frame region
+const BLEND_OP_OVER: u8 = 1;
+
+const TRANSPARENT_BLACK: RGBA16 = RGBA16::new(0, 0, 0, 0);
+
+/// How the region of a frame is disposed of before rendering the next frame
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DisposeOp {
+    /// Leave the region as it is
+    #[default]
+    None = DISPOSE_OP_NONE,
+    /// Clear the region to transparent black
+    Background = DISPOSE_OP_BACKGROUND,
+    /// Revert the region to its contents before the frame was rendered
+    Previous = DISPOSE_OP_PREVIOUS,
+}
+
+/// How a frame is rendered onto its region
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BlendOp {
+    /// Replace the region with the frame
+    #[default]
+    Source = BLEND_OP_SOURCE,
+    /// Composite the frame over the region
+    Over = BLEND_OP_OVER,
+}
+
+/// Position, timing and operations of a frame in an animated png
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+    /// X offset of the frame within the image
+    pub x_offset: u32,
+    /// Y offset of the frame within the image
+    pub y_offset: u32,
+    /// Numerator of the frame delay, in seconds
+    pub delay_num: u16,
+    /// Denominator of the frame delay, in seconds, where 0 is treated as 100
+    pub delay_den: u16,
+    /// How the frame is disposed of before rendering the next frame
+    pub dispose_op: DisposeOp,
+    /// How the frame is rendered
+    pub blend_op: BlendOp,
+}
+
 #[derive(Debug, Clone)]
 /// Animated PNG frame
 pub struct Frame {
@@ -48,6 +115,23 @@ impl Frame {
         })
     }
 
+    /// Construct a new Frame from an image, compressed quickly as it is expected to be optimized later
+    pub fn from_image(image: &PngImage, control: &FrameControl) -> PngResult<Frame> {
+        // Compress with fastest compression level - will be recompressed during optimization
+        let deflater = Deflaters::Libdeflater { compression: 1 };
+        Ok(Frame {
+            width: image.ihdr.width,
+            height: image.ihdr.height,
+            x_offset: control.x_offset,
+            y_offset: control.y_offset,
+            delay_num: control.delay_num,
+            delay_den: control.delay_den,
+            dispose_op: control.dispose_op as u8,
+            blend_op: control.blend_op as u8,
+            data: deflater.deflate(&image.filter_image(RowFilter::None, false), None)?,
+        })
+    }
+
     /// Construct the data for a fcTL chunk using the given sequence number
     #[must_use]
     pub fn fctl_data(&self, sequence_number: u32) -> Vec<u8> {
@@ -64,6 +148,16 @@ impl Frame {
         byte_data
     }
 
+    /// Decode the frame data using the format of the main image
+    pub fn image(&self, ihdr: &IhdrData) -> PngResult<PngImage> {
+        let ihdr = IhdrData {
+            width: self.width,
+            height: self.height,
+            ..ihdr.clone()
+        };
+        PngImage::new(ihdr, &self.data)
+    }
+
     /// Construct the data for a fdAT chunk using the given sequence number
     #[must_use]
     pub fn fdat_data(&self, sequence_number: u32) -> Vec<u8> {
@@ -73,3 +167,695 @@ impl Frame {
         byte_data
     }
 }
+
+/// Validate the animation chunks of an APNG, repairing them if `fix_errors` is set
+///
+/// The acTL and default fcTL chunks must come before the image data, every frame must be within
+/// the bounds of the image, and the acTL chunk must declare the actual number of frames.
+pub fn validate_animation(
+    aux_chunks: &mut Vec<Chunk>,
+    frames: &mut Vec<Frame>,
+    ihdr: &IhdrData,
+    fix_errors: bool,
+) -> PngResult<()> {
+    // Report a problem, returning an error unless it is to be repaired
+    let repair = |problem: &str, action: &str| {
+        if fix_errors {
+            warn!("{problem}, {action}");
+            Ok(())
+        } else {
+            Err(PngError::new(problem))
+        }
+    };
+    let Some(mut actl) = aux_chunks.iter().position(|c| &c.name == b"acTL") else {
+        return Ok(());
+    };
+    let idat = aux_chunks.iter().position(|c| &c.name == b"IDAT");
+    if idat.is_some_and(|idat| actl > idat) {
+        repair("APNG acTL chunk is after the image data", "moving it")?;
+        let chunk = aux_chunks.remove(actl);
+        actl = idat.unwrap();
+        aux_chunks.insert(actl, chunk);
+    }
+    if aux_chunks[actl].data.len() != 8 {
+        repair("APNG acTL chunk has an invalid length", "replacing it")?;
+        aux_chunks[actl].data = vec![0; 8];
+    }
+
+    // The default image can only have one fcTL chunk, which must cover the whole image
+    let mut default_fctls = aux_chunks
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| &c.name == b"fcTL")
+        .map(|(i, _)| i);
+    if let Some(idx) = default_fctls.next() {
+        if default_fctls.next().is_some() {
+            repair(
+                "APNG default image has multiple fcTL chunks",
+                "removing all but the first",
+            )?;
+            let mut seen = false;
+            aux_chunks.retain(|c| &c.name != b"fcTL" || !std::mem::replace(&mut seen, true));
+        }
+        let mut frame = Frame::from_fctl_data(&aux_chunks[idx].data)?;
+        if (frame.width, frame.height, frame.x_offset, frame.y_offset)
+            != (ihdr.width, ihdr.height, 0, 0)
+        {
+            repair(
+                "APNG default image frame does not match the image size",
+                "resizing it",
+            )?;
+            (frame.width, frame.height, frame.x_offset, frame.y_offset) =
+                (ihdr.width, ihdr.height, 0, 0);
+            aux_chunks[idx].data = frame.fctl_data(read_be_u32(&aux_chunks[idx].data[0..4]));
+        }
+    }
+    let num_default = aux_chunks.iter().filter(|c| &c.name == b"fcTL").count();
+
+    let mut valid = Vec::with_capacity(frames.len());
+    for (i, mut frame) in std::mem::take(frames).into_iter().enumerate() {
+        if validate_frame(&mut frame, num_default + i, ihdr, &repair)? {
+            valid.push(frame);
+        }
+    }
+    *frames = valid;
+
+    let actl = aux_chunks.iter().position(|c| &c.name == b"acTL").unwrap();
+    let declared = read_be_u32(&aux_chunks[actl].data[0..4]);
+    let num_frames = (num_default + frames.len()) as u32;
+    if num_frames == 0 {
+        repair("APNG has no frames", "removing the acTL chunk")?;
+        aux_chunks.remove(actl);
+    } else if declared != num_frames {
+        repair(
+            &format!("APNG acTL chunk declares {declared} frames but there are {num_frames}"),
+            "correcting it",
+        )?;
+        aux_chunks[actl].data[0..4].copy_from_slice(&num_frames.to_be_bytes());
+    }
+    Ok(())
+}
+
+/// Validate a frame of an APNG, returning false if it should be removed
+fn validate_frame(
+    frame: &mut Frame,
+    number: usize,
+    ihdr: &IhdrData,
+    repair: &impl Fn(&str, &str) -> PngResult<()>,
+) -> PngResult<bool> {
+    if frame.data.is_empty() {
+        repair(&format!("APNG frame {number} has no data"), "removing it")?;
+        return Ok(false);
+    }
+    if frame.dispose_op > DISPOSE_OP_PREVIOUS {
+        repair(
+            &format!("APNG frame {number} has an invalid dispose op"),
+            "leaving its region as it is",
+        )?;
+        frame.dispose_op = DISPOSE_OP_NONE;
+    }
+    if frame.blend_op > BLEND_OP_OVER {
+        repair(
+            &format!("APNG frame {number} has an invalid blend op"),
+            "replacing its region",
+        )?;
+        frame.blend_op = BLEND_OP_SOURCE;
+    }
+    if frame.width == 0
+        || frame.height == 0
+        || frame.x_offset >= ihdr.width
+        || frame.y_offset >= ihdr.height
+    {
+        repair(
+            &format!("APNG frame {number} is outside the image"),
+            "removing it",
+        )?;
+        return Ok(false);
+    }
+    if u64::from(frame.x_offset) + u64::from(frame.width) > u64::from(ihdr.width)
+        || u64::from(frame.y_offset) + u64::from(frame.height) > u64::from(ihdr.height)
+    {
+        repair(
+            &format!("APNG frame {number} extends beyond the image"),
+            "cropping it",
+        )?;
+        if crop_frame(frame, ihdr).is_none() {
+            warn!("APNG frame {number} could not be decoded, removing it");
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Crop a frame to the bounds of the image
+fn crop_frame(frame: &mut Frame, ihdr: &IhdrData) -> Option<()> {
+    let width = frame.width.min(ihdr.width - frame.x_offset);
+    let height = frame.height.min(ihdr.height - frame.y_offset);
+    let pixels = rgba16_pixels(&frame.image(ihdr).ok()?)?;
+    let cropped: Vec<_> = pixels
+        .chunks(frame.width as usize)
+        .take(height as usize)
+        .flat_map(|row| &row[..width as usize])
+        .copied()
+        .collect();
+    let image = image_from_rgba16(&cropped, width, height, ihdr)?;
+    // Compress with fastest compression level - will be recompressed during optimization
+    let deflater = Deflaters::Libdeflater { compression: 1 };
+    frame.data = deflater
+        .deflate(&image.filter_image(RowFilter::None, false), None)
+        .ok()?;
+    frame.width = width;
+    frame.height = height;
+    Some(())
+}
+
+/// Combine images of the same format into a single progressive image, for analysis of all pixels
+///
+/// The images are stacked vertically, with each row padded to the widest image by repeating its
+/// last pixel. Depths lower than 8 are expanded to 8 bits.
+#[must_use]
+pub fn stacked_image(images: &[&PngImage]) -> Option<PngImage> {
+    let width = images.iter().map(|png| png.ihdr.width).max()?;
+    let mut height = 0;
+    let mut data = Vec::new();
+    let mut ihdr = None;
+    for png in images {
+        let progressive = png.change_interlacing(Interlacing::None);
+        let png = progressive.as_ref().unwrap_or(png);
+        let expanded = expanded_bit_depth_to_8(png);
+        let png = expanded.as_ref().unwrap_or(png);
+        let bpp = png.ihdr.bpp() / 8;
+        for line in png.scan_lines(false) {
+            let last = line.data.get(line.data.len().checked_sub(bpp)?..)?;
+            data.extend_from_slice(line.data);
+            for _ in line.num_pixels..width as usize {
+                data.extend_from_slice(last);
+            }
+        }
+        height += png.ihdr.height;
+        ihdr.get_or_insert_with(|| png.ihdr.clone());
+    }
+    Some(PngImage {
+        data,
+        ihdr: IhdrData {
+            width,
+            height,
+            interlaced: Interlacing::None,
+            ..ihdr?
+        },
+    })
+}
+
+/// A rectangular region of the canvas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Region {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Region {
+    fn of(frame: &Frame) -> Self {
+        Self {
+            x: frame.x_offset as usize,
+            y: frame.y_offset as usize,
+            width: frame.width as usize,
+            height: frame.height as usize,
+        }
+    }
+
+    /// Iterate over the canvas indices of the region, row by row
+    fn indices(self, canvas_width: usize) -> impl Iterator<Item = usize> {
+        (self.y..self.y + self.height)
+            .flat_map(move |y| (self.x..self.x + self.width).map(move |x| y * canvas_width + x))
+    }
+}
+
+/// Dispose of a frame region on the canvas, where `base` is the canvas before the frame was rendered
+fn dispose(canvas: &mut [RGBA16], width: usize, region: Region, dispose_op: u8, base: &[RGBA16]) {
+    match dispose_op {
+        DISPOSE_OP_NONE => {}
+        DISPOSE_OP_PREVIOUS => {
+            for i in region.indices(width) {
+                canvas[i] = base[i];
+            }
+        }
+        _ => {
+            for i in region.indices(width) {
+                canvas[i] = TRANSPARENT_BLACK;
+            }
+        }
+    }
+}
+
+/// Render a frame onto the canvas, returning `None` if the result would depend on how the
+/// decoder rounds partially transparent blending
+fn render(
+    canvas: &mut [RGBA16],
+    width: usize,
+    region: Region,
+    pixels: &[RGBA16],
+    blend_op: u8,
+) -> Option<()> {
+    for (i, &src) in region.indices(width).zip(pixels) {
+        let dst = &mut canvas[i];
+        if blend_op == BLEND_OP_SOURCE || src.a == u16::MAX || dst.a == 0 {
+            *dst = src;
+        } else if src.a != 0 {
+            return None;
+        }
+    }
+    Some(())
+}
+
+/// Find the smallest region containing every pixel that differs between two canvases
+fn changed_region(before: &[RGBA16], after: &[RGBA16], width: usize) -> Option<Region> {
+    let (mut x0, mut y0, mut x1, mut y1) = (usize::MAX, usize::MAX, 0, 0);
+    for (i, (a, b)) in before.iter().zip(after).enumerate() {
+        if a != b {
+            let (x, y) = (i % width, i / width);
+            x0 = x0.min(x);
+            y0 = y0.min(y);
+            x1 = x1.max(x);
+            y1 = y1.max(y);
+        }
+    }
+    (x0 != usize::MAX).then(|| Region {
+        x: x0,
+        y: y0,
+        width: x1 - x0 + 1,
+        height: y1 - y0 + 1,
+    })
+}
+
+/// Get a fully transparent pixel that can be represented in the given format
+fn transparent_pixel(ihdr: &IhdrData) -> Option<RGBA16> {
+    // Scale samples to 16 bits, as for the pixels of an image
+    let scale = |v: u16| (u32::from(v) * (65535 / ((1 << ihdr.bit_depth as u32) - 1))) as u16;
+    match &ihdr.color_type {
+        ColorType::GrayscaleAlpha | ColorType::RGBA => Some(TRANSPARENT_BLACK),
+        ColorType::Indexed { palette } => palette.iter().find(|c| c.a == 0).map(|c| {
+            let wide = |v: u8| u16::from(v) * 257;
+            RGBA16::new(wide(c.r), wide(c.g), wide(c.b), 0)
+        }),
+        ColorType::Grayscale {
+            transparent_shade: Some(t),
+        } => Some(RGBA16::new(scale(*t), scale(*t), scale(*t), 0)),
+        ColorType::RGB {
+            transparent_color: Some(t),
+        } => Some(RGBA16::new(scale(t.r), scale(t.g), scale(t.b), 0)),
+        _ => None,
+    }
+}
+
+/// A candidate encoding of a frame
+struct Candidate {
+    /// Dispose operation of the previous frame
+    dispose_op: u8,
+    blend_op: u8,
+    region: Region,
+    data: Vec<u8>,
+    /// The canvas before this frame is rendered
+    base: Vec<RGBA16>,
+}
+
+/// Merge consecutive frames of an APNG that display the same image, returning true if successful
+///
+/// The delays of merged frames are summed, and the dispose op of the remaining frame is chosen to
+/// leave the canvas as it was. If only the default image remains, the APNG becomes a plain PNG.
+pub fn collapse_frames(png: &mut PngData, deadline: &Deadline) -> bool {
+    let Some((default_fctl, frames)) = animation_frames(png) else {
+        return false;
+    };
+    if frames.len() == 1 {
+        if default_fctl.is_none() {
+            return false;
+        }
+        remove_animation(png);
+        return true;
+    }
+    let Some(Animation { frames, pixels, .. }) = Animation::decode(png) else {
+        return false;
+    };
+    let width = png.raw.ihdr.width as usize;
+    let height = png.raw.ihdr.height as usize;
+
+    // Canvas after rendering the previous frame, and before rendering it
+    let mut canvas = vec![TRANSPARENT_BLACK; width * height];
+    let mut base = canvas.clone();
+    let first = Region::of(&frames[0]);
+    if render(&mut canvas, width, first, &pixels[0], frames[0].blend_op).is_none() {
+        return false;
+    }
+    // The last frame that is kept, with its canvas after and before rendering
+    let mut kept = vec![frames[0].clone()];
+    let mut kept_canvas = canvas.clone();
+    let mut kept_base = base.clone();
+    for i in 1..frames.len() {
+        if deadline.passed() {
+            return false;
+        }
+        let mut next = canvas.clone();
+        let prev = &frames[i - 1];
+        dispose(&mut next, width, Region::of(prev), prev.dispose_op, &base);
+        let next_base = next.clone();
+        let frame = &frames[i];
+        if render(
+            &mut next,
+            width,
+            Region::of(frame),
+            &pixels[i],
+            frame.blend_op,
+        )
+        .is_none()
+        {
+            return false;
+        }
+
+        let last = kept.last_mut().unwrap();
+        let merged = (next == kept_canvas)
+            .then(|| {
+                // The dispose op of the last frame doesn't matter
+                if i == frames.len() - 1 {
+                    return Some(last.dispose_op);
+                }
+                let mut disposed = next.clone();
+                dispose(
+                    &mut disposed,
+                    width,
+                    Region::of(frame),
+                    frame.dispose_op,
+                    &next_base,
+                );
+                let region = Region::of(last);
+                [DISPOSE_OP_NONE, DISPOSE_OP_BACKGROUND, DISPOSE_OP_PREVIOUS]
+                    .into_iter()
+                    .find(|&dispose_op| {
+                        let mut merged = kept_canvas.clone();
+                        dispose(&mut merged, width, region, dispose_op, &kept_base);
+                        merged == disposed
+                    })
+            })
+            .flatten()
+            .zip(summed_delay(last, frame));
+        if let Some((dispose_op, (delay_num, delay_den))) = merged {
+            last.dispose_op = dispose_op;
+            last.delay_num = delay_num;
+            last.delay_den = delay_den;
+        } else {
+            kept.push(frame.clone());
+            kept_canvas.clone_from(&next);
+            kept_base.clone_from(&next_base);
+        }
+        base = next_base;
+        canvas = next;
+    }
+
+    let dispose_ops: Vec<_> = kept.iter().map(|f| f.dispose_op).collect();
+    if kept.len() == frames.len()
+        || (1..kept.len() - 1).any(|i| {
+            dispose_ops[i] == DISPOSE_OP_PREVIOUS && !restores_consistently(&dispose_ops[..i])
+        })
+    {
+        return false;
+    }
+    debug!(
+        "Merged {} identical APNG frames, {} remaining",
+        frames.len() - kept.len(),
+        kept.len()
+    );
+    if let Some(idx) = default_fctl {
+        let default = kept.remove(0);
+        png.aux_chunks[idx].data = default.fctl_data(0);
+        if kept.is_empty() {
+            remove_animation(png);
+        }
+    }
+    png.frames = kept;
+    true
+}
+
+/// Remove the animation chunks of a single-frame APNG
+fn remove_animation(png: &mut PngData) {
+    png.aux_chunks
+        .retain(|c| !matches!(&c.name, b"acTL" | b"fcTL"));
+    debug!("Converted single-frame APNG to PNG");
+}
+
+/// Sum the delays of two frames, if the result can be represented
+fn summed_delay(a: &Frame, b: &Frame) -> Option<(u16, u16)> {
+    // A denominator of 0 is treated as 100
+    let den = |f: &Frame| match f.delay_den {
+        0 => 100,
+        d => u64::from(d),
+    };
+    let (a_den, b_den) = (den(a), den(b));
+    let lcm = a_den / gcd(a_den, b_den) * b_den;
+    let num = u64::from(a.delay_num) * (lcm / a_den) + u64::from(b.delay_num) * (lcm / b_den);
+    let divisor = gcd(num, lcm);
+    Some((
+        u16::try_from(num / divisor).ok()?,
+        u16::try_from(lcm / divisor).ok()?,
+    ))
+}
+
+pub(crate) fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Whether disposing to the previous canvas, following frames with the given dispose ops, gives the
+/// same result in all decoders
+///
+/// Some decoders don't clear the region of a frame disposed to the background before saving the
+/// canvas that a later frame is restored to.
+fn restores_consistently(dispose_ops: &[u8]) -> bool {
+    let last = dispose_ops
+        .iter()
+        .rev()
+        .find(|&&d| d != DISPOSE_OP_PREVIOUS);
+    matches!(last, None | Some(&DISPOSE_OP_NONE))
+}
+
+/// The frames of an APNG decoded to 16-bit RGBA
+struct Animation {
+    /// Index of the fcTL chunk of the default image, if it is the first frame
+    default_fctl: Option<usize>,
+    frames: Vec<Frame>,
+    pixels: Vec<Vec<RGBA16>>,
+}
+
+impl Animation {
+    /// Decode an APNG with at least two frames, all of which must be within the canvas
+    ///
+    /// Returns `None` if the animation can't be decoded or its rendering is decoder-dependent.
+    fn decode(png: &PngData) -> Option<Self> {
+        let ihdr = &png.raw.ihdr;
+        let (default_fctl, frames) = animation_frames(png)?;
+        if frames.len() < 2
+            || frames.iter().any(|f| {
+                f.width == 0
+                    || f.height == 0
+                    || u64::from(f.x_offset) + u64::from(f.width) > u64::from(ihdr.width)
+                    || u64::from(f.y_offset) + u64::from(f.height) > u64::from(ihdr.height)
+            })
+        {
+            return None;
+        }
+        let dispose_ops: Vec<_> = frames.iter().map(|f| f.dispose_op).collect();
+        if (1..frames.len() - 1).any(|i| {
+            dispose_ops[i] == DISPOSE_OP_PREVIOUS && !restores_consistently(&dispose_ops[..i])
+        }) {
+            debug!("APNG disposal is decoder-dependent, leaving frames as they are");
+            return None;
+        }
+
+        let pixels = frames
+            .par_iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                if i == 0 && default_fctl.is_some() {
+                    rgba16_pixels(&png.raw)
+                } else {
+                    rgba16_pixels(&frame.image(ihdr).ok()?)
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            default_fctl,
+            frames,
+            pixels,
+        })
+    }
+}
+
+/// Get the frames of an APNG, including the default image if it has an fcTL chunk, along with the
+/// index of that chunk
+fn animation_frames(png: &PngData) -> Option<(Option<usize>, Vec<Frame>)> {
+    let default_fctl = png.aux_chunks.iter().position(|c| &c.name == b"fcTL");
+    let mut frames = Vec::with_capacity(png.frames.len() + 1);
+    if let Some(idx) = default_fctl {
+        frames.push(Frame::from_fctl_data(&png.aux_chunks[idx].data).ok()?);
+    }
+    frames.extend(png.frames.iter().cloned());
+    Some((default_fctl, frames))
+}
+
+/// Re-derive each frame of an APNG from the composited animation, returning true if successful
+///
+/// Each frame is reduced to the region that changed since the previous frame, choosing the dispose
+/// operation of the previous frame and the blend operation of the frame that compress best. When
+/// blending over the previous frame, unchanged pixels are made transparent. The first frame and the
+/// rendered result are unchanged. The new frames are only kept if they are smaller in total.
+pub fn rediff_frames(png: &mut PngData, deflater: Deflaters, deadline: &Deadline) -> bool {
+    let ihdr = png.raw.ihdr.clone();
+    let width = ihdr.width as usize;
+    let height = ihdr.height as usize;
+    let Some(Animation {
+        default_fctl,
+        frames,
+        pixels,
+    }) = Animation::decode(png)
+    else {
+        return false;
+    };
+
+    let encode = |pixels: &[RGBA16], region: Region| {
+        let image = image_from_rgba16(pixels, region.width as u32, region.height as u32, &ihdr)?;
+        deflater
+            .deflate(&image.filter_image(RowFilter::None, false), None)
+            .ok()
+    };
+    let transparent = transparent_pixel(&ihdr);
+
+    // Canvas after rendering the previous frame, in both the original and new animations
+    let mut canvas = vec![TRANSPARENT_BLACK; width * height];
+    let first = Region::of(&frames[0]);
+    if render(&mut canvas, width, first, &pixels[0], frames[0].blend_op).is_none() {
+        return false;
+    }
+    // Canvas of the original animation before rendering the previous frame
+    let mut orig_base = vec![TRANSPARENT_BLACK; width * height];
+    // Canvas of the new animation before rendering the previous frame
+    let mut new_base = orig_base.clone();
+    let mut new_frames = frames.clone();
+    let mut orig_size = 0;
+    let mut new_size = 0;
+    for i in 1..frames.len() {
+        if deadline.passed() {
+            return false;
+        }
+        // Dispose of the previous frame in the original animation, then render this frame
+        let mut next = canvas.clone();
+        let prev = &frames[i - 1];
+        dispose(
+            &mut next,
+            width,
+            Region::of(prev),
+            prev.dispose_op,
+            &orig_base,
+        );
+        let base = next.clone();
+        if render(
+            &mut next,
+            width,
+            Region::of(&frames[i]),
+            &pixels[i],
+            frames[i].blend_op,
+        )
+        .is_none()
+        {
+            return false;
+        }
+
+        // Try each way of disposing the previous frame in the new animation
+        let new_prev_region = Region::of(&new_frames[i - 1]);
+        let mut dispose_ops = vec![DISPOSE_OP_NONE, DISPOSE_OP_BACKGROUND];
+        // A dispose op of previous on the first frame is treated as background
+        let new_ops: Vec<_> = new_frames[..i - 1].iter().map(|f| f.dispose_op).collect();
+        if i > 1 && restores_consistently(&new_ops) {
+            dispose_ops.push(DISPOSE_OP_PREVIOUS);
+        }
+        let candidates = dispose_ops.into_par_iter().flat_map(|dispose_op| {
+            let mut base = canvas.clone();
+            dispose(&mut base, width, new_prev_region, dispose_op, &new_base);
+            // An empty frame is not allowed, so use a single pixel if nothing changed
+            let region = changed_region(&base, &next, width).unwrap_or(Region {
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1,
+            });
+            let source: Vec<_> = region.indices(width).map(|j| next[j]).collect();
+            let mut results = Vec::with_capacity(2);
+            if let Some(data) = encode(&source, region) {
+                results.push(Candidate {
+                    dispose_op,
+                    blend_op: BLEND_OP_SOURCE,
+                    region,
+                    data,
+                    base: base.clone(),
+                });
+            }
+            // Blending over requires a transparent pixel, and every changed pixel to be opaque
+            if let Some(transparent) = transparent {
+                let over: Option<Vec<_>> = region
+                    .indices(width)
+                    .map(|j| match next[j] {
+                        px if px == base[j] => Some(transparent),
+                        px if px.a == u16::MAX => Some(px),
+                        _ => None,
+                    })
+                    .collect();
+                if let Some(data) = over.and_then(|over| encode(&over, region)) {
+                    results.push(Candidate {
+                        dispose_op,
+                        blend_op: BLEND_OP_OVER,
+                        region,
+                        data,
+                        base,
+                    });
+                }
+            }
+            results
+        });
+        let Some(best) = candidates.min_by_key(|c| c.data.len()) else {
+            return false;
+        };
+        let Some(orig_data) = encode(&pixels[i], Region::of(&frames[i])) else {
+            return false;
+        };
+        orig_size += orig_data.len();
+        new_size += best.data.len();
+
+        new_frames[i - 1].dispose_op = best.dispose_op;
+        let frame = &mut new_frames[i];
+        frame.x_offset = best.region.x as u32;
+        frame.y_offset = best.region.y as u32;
+        frame.width = best.region.width as u32;
+        frame.height = best.region.height as u32;
+        frame.blend_op = best.blend_op;
+        frame.data = best.data;
+        new_base = best.base;
+        orig_base = base;
+        canvas = next;
+    }
+
+    if new_size >= orig_size {
+        debug!("Re-diffed APNG frames were not smaller ({new_size} >= {orig_size} bytes)");
+        return false;
+    }
+    debug!(
+        "Re-diffed APNG frames: {new_size} bytes ({} bytes decrease)",
+        orig_size - new_size
+    );
+    // The default image keeps its data, only its dispose op may have changed
+    if let Some(idx) = default_fctl {
+        png.aux_chunks[idx].data[24] = new_frames.remove(0).dispose_op;
+    }
+    png.frames = new_frames;
+    true
+}
@@ -25,20 +25,22 @@ pub fn build_command() -> Command {
         )
         .arg(
             Arg::new("optimization")
-                .help("Optimization level (0-6, or max)")
+                .help("Optimization level (0-7, max, or max+)")
                 .long_help("\
 Set the optimization level preset. The default level 2 is quite fast and provides good \
 compression. Lower levels are faster, higher levels provide better compression, though \
 with increasingly diminishing returns.
 
-    0   => --zc 5 --fast               (1 trial, determined heuristically)
-    1   => --zc 10 --fast              (1 trial, determined heuristically)
-    2   => --zc 11 -f 0,1,6,7 --fast   (4 fast trials, 1 main trial)
-    3   => --zc 11 -f 0,7,8,9          (4 trials)
-    4   => --zc 12 -f 0,7,8,9          (4 trials)
-    5   => --zc 12 -f 0,1,2,5,6,7,8,9  (8 trials)
-    6   => --zc 12 -f 0-9              (10 trials)
-    max =>                             (stable alias for the max level)
+    0    => --zc 5 --fast               (1 trial, determined heuristically)
+    1    => --zc 10 --fast              (1 trial, determined heuristically)
+    2    => --zc 11 -f 0,1,6,7 --fast   (4 fast trials, 1 main trial)
+    3    => --zc 11 -f 0,7,8,9          (4 trials)
+    4    => --zc 12 -f 0,7,8,9          (4 trials)
+    5    => --zc 12 -f 0,1,2,5,6,7,8,9  (8 trials)
+    6    => --zc 12 -f 0-9              (10 trials)
+    7    => --zc 12 -f 0-9,11           (11 trials, including a slow genetic search)
+    max  =>                             (alias for the highest level, currently 7)
+    max+ => --exhaustive                (highest level, with every reduction fully trialled)
 
 Manually specifying a compression option (zc, f, etc.) will override the optimization \
 preset, regardless of the order you write the arguments.")
@@ -46,7 +48,7 @@ preset, regardless of the order you write the arguments.")
                 .long("opt")
                 .value_name("level")
                 .default_value("2")
-                .value_parser(["0", "1", "2", "3", "4", "5", "6", "max"])
+                .value_parser(["0", "1", "2", "3", "4", "5", "6", "7", "max", "max+"])
                 .hide_possible_values(true),
         )
         .arg(
@@ -150,6 +152,118 @@ image appearance.")
                 .conflicts_with("strip")
                 .conflicts_with("strip-safe"),
         )
+        .arg(
+            Arg::new("strip-text")
+                .help("Strip text chunks with keywords in the comma-separated list")
+                .long_help("\
+Strip tEXt, zTXt and iTXt chunks with keywords in the comma-separated list. Text chunks with \
+other keywords are kept or stripped according to the other options.
+
+E.g. '--strip-text Software,Comment,parameters,workflow' will remove the software and comment \
+text, along with prompts embedded by image generation tools.")
+                .long("strip-text")
+                .value_name("list")
+                .conflicts_with("keep-text"),
+        )
+        .arg(
+            Arg::new("keep-text")
+                .help("Strip all text chunks except those with keywords in the comma-separated list")
+                .long_help("\
+Strip all tEXt, zTXt and iTXt chunks except those with keywords in the comma-separated list. \
+Text chunks with these keywords are kept even if the chunk type is stripped by other options.
+
+E.g. '--strip safe --keep-text Copyright,Author,License' will strip all metadata except for \
+the copyright, author and license text.")
+                .long("keep-text")
+                .value_name("list")
+                .conflicts_with("strip-text"),
+        )
+        .arg(
+            Arg::new("strip-exif")
+                .help("Strip groups of Exif tags in the comma-separated list")
+                .long_help("\
+Strip groups of tags from the Exif data, as a comma-separated list, where each group is one \
+of:
+
+    gps        =>  The GPS location and any other GPS data
+    serial     =>  The serial numbers of the camera body and lens
+    thumbnail  =>  The thumbnail image
+
+All other tags, such as the orientation and copyright, are kept. If the Exif data can't be \
+parsed, the eXIf chunk is removed instead. This has no effect if the eXIf chunk is stripped.
+
+E.g. '--strip-exif gps,thumbnail' will remove the location and thumbnail of the image.")
+                .long("strip-exif")
+                .value_name("list"),
+        )
+        .arg(
+            Arg::new("strip-xmp")
+                .help("Strip XMP properties in the comma-separated list")
+                .long_help("\
+Strip properties from the XMP data, as a comma-separated list of qualified names. Use \
+'prefix:*' to strip all properties of a namespace. Prefixes are matched as written in the \
+XMP data. The XMP data will also be compressed if this makes it smaller. This has no effect \
+if the iTXt chunk is stripped.
+
+E.g. '--strip-xmp photoshop:History,xmpMM:History,xmp:Thumbnails' will remove the editing \
+history and thumbnails, keeping other properties such as the copyright and license.")
+                .long("strip-xmp")
+                .value_name("list"),
+        )
+        .arg(
+            Arg::new("set-text")
+                .help("Set a text chunk, may be repeated")
+                .long_help("\
+Set a text chunk with the given keyword, replacing any existing text chunks with the same \
+keyword. Text that can't be represented in Latin-1 is stored in an iTXt chunk. This may be \
+repeated to set multiple keywords.
+
+E.g. '--set-text \"Copyright=(c) Example\"' will set the copyright text.")
+                .long("set-text")
+                .value_name("keyword=text")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("set-itxt")
+                .help("Set an international (UTF-8) text chunk, may be repeated")
+                .long_help("\
+Set an iTXt chunk with the given keyword, replacing any existing text chunks with the same \
+keyword. The text is stored as UTF-8. This may be repeated to set multiple keywords.")
+                .long("set-itxt")
+                .value_name("keyword=text")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("set-phys")
+                .help("Set the physical pixel dimensions (e.g. 300dpi)")
+                .long_help("\
+Set the physical pixel dimensions, replacing any existing pHYs chunk, where <size> is one of:
+
+    <n>dpi    =>  Pixels per inch, e.g. '300dpi'
+    <n>ppm    =>  Pixels per meter, e.g. '11811ppm'
+    <x>:<y>   =>  The aspect ratio of the pixels only, e.g. '1:2'
+
+A different horizontal and vertical resolution can be given as '<x>x<y>dpi' or '<x>x<y>ppm'.")
+                .long("set-phys")
+                .value_name("size"),
+        )
+        .arg(
+            Arg::new("set-srgb")
+                .help("Mark the image as sRGB, with an optional rendering intent")
+                .long_help("\
+Set an sRGB chunk with the given rendering intent, replacing any existing sRGB or iCCP chunk. \
+The intent is one of 'perceptual', 'relative', 'saturation' or 'absolute'.
+
+E.g. '--set-srgb=relative' will set the relative colorimetric intent.
+
+[default: perceptual]")
+                .long("set-srgb")
+                .value_name("intent")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("perceptual")
+                .value_parser(["perceptual", "relative", "saturation", "absolute"]),
+        )
         .arg(
             Arg::new("alpha")
                 .help("Perform additional alpha channel optimization")
@@ -162,16 +276,38 @@ transformation and may be unsuitable for some applications.")
                 .long("alpha")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("alpha-fill")
+                .help("Transparent pixel colors to try [default: predictor]")
+                .long_help("\
+Set the strategies to try for the color values of fully transparent pixels, as a \
+comma-separated list. Each strategy is evaluated separately and the smallest result is kept. \
+This option requires '--alpha' to be set.
+
+    black      =>  Set the color to black
+    left       =>  Copy the color of the pixel to the left
+    up         =>  Copy the color of the pixel above
+    predictor  =>  Match the prediction of the delta filter for each line
+    nearest    =>  Copy the color of the nearest opaque pixel
+
+E.g. '--alpha-fill predictor,nearest' will try both of these strategies.
+
+[default: predictor]")
+                .long("alpha-fill")
+                .value_name("list")
+                .requires("alpha"),
+        )
         .arg(
             // Note: The default value is not explicitly set here, as it is dependant on the `--nx` flag.
             Arg::new("interlace")
-                .help("Set PNG interlacing type (0, 1, keep) [default: 0]")
+                .help("Set interlacing type (0, 1, keep, auto) [default: 0]")
                 .long_help("\
 Set the PNG interlacing type, where <type> is one of:
 
     0     =>  Remove interlacing from all images that are processed
     1     =>  Apply Adam7 interlacing on all images that are processed
     keep  =>  Keep the existing interlacing type of each image
+    auto  =>  Try both interlacing types and keep whichever is smaller
 
 Note that interlacing can add 25-50% to the size of an optimized image. Only use it if you \
 believe the benefits outweigh the costs for your use case.
@@ -180,9 +316,22 @@ believe the benefits outweigh the costs for your use case.
                 .short('i')
                 .long("interlace")
                 .value_name("type")
-                .value_parser(["0", "1", "keep"])
+                .value_parser(["0", "1", "keep", "auto"])
                 .hide_possible_values(true),
         )
+        .arg(
+            Arg::new("interlace-tolerance")
+                .help("Prefer interlacing if within <percent> in auto mode")
+                .long_help("\
+When using `-i auto`, prefer the interlaced version if it is no more than <percent> larger \
+than the non-interlaced version. This can be used to favor progressive display when the cost \
+is small.
+
+[default: 0]")
+                .long("interlace-tolerance")
+                .value_name("percent")
+                .value_parser(value_parser!(f32)),
+        )
         .arg(
             Arg::new("scale16")
                 .help("Forcibly reduce 16-bit images to 8-bit (lossy)")
@@ -215,7 +364,7 @@ losslessly.")
         )
         .arg(
             Arg::new("filters")
-                .help("Filters to try (0-9; see '--help' for details)")
+                .help("Filters to try (0-11; see '--help' for details)")
                 .long_help("\
 Perform compression trials with each of the given filter types. You can specify a \
 comma-separated list, or a range of values. E.g. '-f 0-3' is the same as '-f 0,1,2,3'.
@@ -228,17 +377,47 @@ PNG delta filters (apply the same filter to every line)
     4  =>  Paeth
 
 Heuristic strategies (try to find the best delta filter for each line)
-    5  =>  MinSum    Minimum sum of absolute differences
-    6  =>  Entropy   Highest Shannon entropy
-    7  =>  Bigrams   Lowest count of distinct bigrams
-    8  =>  BigEnt    Highest Shannon entropy of bigrams
-    9  =>  Brute     Smallest compressed size (slow)
+    5  =>  MinSum      Minimum sum of absolute differences
+    6  =>  Entropy     Highest Shannon entropy
+    7  =>  Bigrams     Lowest count of distinct bigrams
+    8  =>  BigEnt      Highest Shannon entropy of bigrams
+    9  =>  Brute       Smallest compressed size (slow)
+    10 =>  BruteModel  Smallest cost in a running compression model of the whole image
+                       (slow, see '--brute-window' and '--brute-level')
+
+Search strategies (try to find the best combination of delta filters for all lines)
+    11 =>  Genetic     Evolve the filters by compressed size, starting from the heuristics
+                       (very slow, limited by the timeout if set)
 
 The default value depends on the optimization level preset.")
                 .short('f')
                 .long("filters")
                 .value_name("list"),
         )
+        .arg(
+            Arg::new("brute-window")
+                .help("BruteModel match window [default: 32768]")
+                .long_help("\
+Set the maximum match distance, in bytes, of the compression model used by the BruteModel \
+filter (-f 10). Smaller windows are faster but less representative of the final compression.
+
+[default: 32768]")
+                .long("brute-window")
+                .value_name("bytes")
+                .value_parser(1..=32768),
+        )
+        .arg(
+            Arg::new("brute-level")
+                .help("BruteModel match effort (1-9) [default: 5]")
+                .long_help("\
+Set the match search effort (1-9) of the compression model used by the BruteModel filter \
+(-f 10). Higher levels are slower but may find better filters.
+
+[default: 5]")
+                .long("brute-level")
+                .value_name("level")
+                .value_parser(1..=9),
+        )
         .arg(
             Arg::new("fast")
                 .help("Use fast filter evaluation")
@@ -249,6 +428,16 @@ CPU cores.")
                 .long("fast")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("exhaustive")
+                .help("Trial every reduction with all filters (very slow)")
+                .long_help("\
+Perform full compression trials of every reduction candidate with each enabled filter, rather \
+than choosing the reductions with a cheaper evaluation first. This can be extremely slow, \
+especially with Zopfli, so consider also setting a `--timeout`.")
+                .long("exhaustive")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("compression")
                 .help("Deflate compression level (0-12)")
@@ -308,8 +497,8 @@ compressed chunks (such as iCCP) will also be disabled. Note that the combinatio
             Arg::new("fix")
                 .help("Disable checksum validation")
                 .long_help("\
-Do not perform checksum validation of PNG chunks. This may allow some files with errors to \
-be processed successfully.")
+Do not perform checksum validation of PNG chunks, and repair invalid APNG animation data. \
+This may allow some files with errors to be processed successfully.")
                 .long("fix")
                 .action(ArgAction::SetTrue),
         )
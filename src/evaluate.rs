@@ -17,14 +17,20 @@ use rayon::prelude::*;
 
 #[cfg(not(feature = "parallel"))]
 use crate::rayon;
-use crate::{atomicmin::AtomicMin, deflate, filters::RowFilter, png::PngImage, Deadline, PngError};
+use crate::{
+    atomicmin::AtomicMin,
+    deflate,
+    filters::{Filter, RowFilter},
+    png::{ModelSettings, PngImage},
+    Deadline, PngError,
+};
 
 pub(crate) struct Candidate {
     pub image: Arc<PngImage>,
     pub data: Vec<u8>,
     pub data_is_compressed: bool,
     pub estimated_output_size: usize,
-    pub filter: RowFilter,
+    pub filter: Filter,
     // For determining tie-breaker
     nth: usize,
 }
@@ -34,7 +40,7 @@ impl Candidate {
         (
             self.estimated_output_size,
             self.image.data.len(),
-            self.filter,
+            self.filter.order(),
             // Prefer the later image added (e.g. baseline, which is always added last)
             usize::MAX - self.nth,
         )
@@ -48,6 +54,9 @@ pub(crate) struct Evaluator {
     deflater: Deflaters,
     optimize_alpha: bool,
     final_round: bool,
+    model: ModelSettings,
+    /// Custom filters, tried in addition to the selected filters
+    custom: Vec<Filter>,
     nth: AtomicUsize,
     executed: Arc<AtomicUsize>,
     best_candidate_size: Arc<AtomicMin>,
@@ -66,6 +75,8 @@ impl Evaluator {
         deflater: Deflaters,
         optimize_alpha: bool,
         final_round: bool,
+        model: ModelSettings,
+        custom: Vec<Filter>,
     ) -> Self {
         #[cfg(feature = "parallel")]
         let eval_channel = unbounded();
@@ -75,6 +86,8 @@ impl Evaluator {
             deflater,
             optimize_alpha,
             final_round,
+            model,
+            custom,
             nth: AtomicUsize::new(0),
             executed: Arc::new(AtomicUsize::new(0)),
             best_candidate_size: Arc::new(AtomicMin::new(None)),
@@ -119,13 +132,34 @@ impl Evaluator {
 
     /// Check if the image is smaller than others, with a description for verbose mode
     pub fn try_image_with_description(&self, image: Arc<PngImage>, description: &str) {
+        self.try_image_with_filters(
+            image,
+            description,
+            self.filters.clone(),
+            self.optimize_alpha,
+        );
+    }
+
+    /// Check if the image is smaller than others, overriding the filters and alpha optimization
+    /// Any custom filters of the evaluator are tried in addition to the given filters
+    pub fn try_image_with_filters(
+        &self,
+        image: Arc<PngImage>,
+        description: &str,
+        filters: IndexSet<RowFilter>,
+        optimize_alpha: bool,
+    ) {
         let nth = self.nth.fetch_add(1, SeqCst);
+        let filters: Vec<_> = filters
+            .into_iter()
+            .map(Filter::Row)
+            .chain(self.custom.iter().cloned())
+            .collect();
         // These clones are only cheap refcounts
         let deadline = self.deadline.clone();
-        let filters = self.filters.clone();
         let deflater = self.deflater;
-        let optimize_alpha = self.optimize_alpha;
         let final_round = self.final_round;
+        let model = self.model;
         let executed = self.executed.clone();
         let best_candidate_size = self.best_candidate_size.clone();
         let description = description.to_string();
@@ -141,11 +175,11 @@ impl Evaluator {
             // which are dangerous to do in side Rayon's loop.
             // Instead, only update (atomic) best size in real time,
             // and the best result later without need for locks.
-            filters_iter.for_each(|&filter| {
+            filters_iter.for_each(|filter| {
                 if deadline.passed() {
                     return;
                 }
-                let filtered = image.filter_image(filter, optimize_alpha);
+                let filtered = image.filter_image_with(filter, optimize_alpha, &deadline, model);
                 let idat_data = deflater.deflate(&filtered, best_candidate_size.get());
                 if let Ok(idat_data) = idat_data {
                     let estimated_output_size = image.estimated_output_size(&idat_data);
@@ -155,7 +189,7 @@ impl Evaluator {
                         data: if final_round { idat_data } else { filtered },
                         data_is_compressed: final_round,
                         estimated_output_size,
-                        filter,
+                        filter: filter.clone(),
                         nth,
                     };
                     best_candidate_size.set_min(estimated_output_size);
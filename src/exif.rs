@@ -0,0 +1,297 @@
+use std::{collections::HashSet, ops::Range};
+
+use indexmap::IndexSet;
+
+/// A group of Exif tags that can be stripped, for use in [`Options`][crate::Options]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub enum ExifGroup {
+    /// The GPS location and any other GPS data
+    Gps,
+    /// The serial numbers of the camera body and lens
+    Serial,
+    /// The thumbnail image
+    Thumbnail,
+}
+
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+const INTEROP_IFD: u16 = 0xA005;
+/// CameraSerialNumber, BodySerialNumber and LensSerialNumber
+const SERIAL_TAGS: [u16; 3] = [0xC62F, 0xA431, 0xA435];
+const STRIP_OFFSETS: u16 = 0x0111;
+const STRIP_BYTE_COUNTS: u16 = 0x0117;
+const JPEG_OFFSET: u16 = 0x0201;
+const JPEG_LENGTH: u16 = 0x0202;
+/// Limit on the nesting and chaining of IFDs
+const MAX_DEPTH: usize = 8;
+/// Limit on the total number of IFD entries visited while walking the structure
+const MAX_ENTRIES: usize = 1 << 16;
+
+/// Strip groups of tags from the data of an eXIf chunk, returning `None` if it can't be parsed
+/// or the tags can't be removed
+///
+/// The remaining data is not moved, as maker notes may contain offsets that can't be updated.
+/// Instead, the removed data is cleared and the end of the data is truncated where possible.
+#[must_use]
+pub fn strip_exif(data: &[u8], groups: &IndexSet<ExifGroup>) -> Option<Vec<u8>> {
+    // Some encoders include the identifier from the JPEG segment, which is kept as is
+    let prefix = if data.starts_with(b"Exif\0\0") { 6 } else { 0 };
+    let mut tiff = Tiff::new(data[prefix..].to_vec())?;
+    let ifd0 = tiff.u32(4)? as usize;
+
+    let mut removed = Vec::new();
+    tiff.strip_ifd(ifd0, groups, &mut removed, &mut Visited::default(), 0)?;
+    if groups.contains(&ExifGroup::Thumbnail) {
+        // The thumbnail is described by the next IFD after the main image
+        let next_pos = ifd0 + 2 + 12 * tiff.u16(ifd0)? as usize;
+        let ifd1 = tiff.u32(next_pos)? as usize;
+        if ifd1 != 0 {
+            tiff.extents(ifd1, &mut removed, &mut Visited::default(), 0)?;
+            tiff.set_u32(next_pos, 0);
+        }
+    }
+
+    // Kept structures may share data with removed ones, so only data that is no longer used by
+    // the header or any IFD is cleared
+    let mut used = Vec::new();
+    used.push(0..8);
+    tiff.extents(ifd0, &mut used, &mut Visited::default(), 0)?;
+    let mut is_used = vec![false; tiff.data.len()];
+    for range in used {
+        is_used[range].fill(true);
+    }
+    for range in removed {
+        for i in range.filter(|&i| !is_used[i]) {
+            tiff.data[i] = 0;
+        }
+    }
+    // Data at the end can only be truncated if nothing else may refer to it
+    let end = is_used.iter().rposition(|&u| u).map_or(0, |i| i + 1);
+    if tiff.data[end..].iter().all(|&b| b == 0) {
+        tiff.data.truncate(end);
+    }
+
+    let mut output = data[..prefix].to_vec();
+    output.extend(tiff.data);
+    Some(output)
+}
+
+/// The IFDs visited while walking the structure, so that malformed data which refers to an IFD
+/// more than once can't make the walk repeat work
+#[derive(Default)]
+struct Visited {
+    offsets: HashSet<usize>,
+    entries: usize,
+}
+
+impl Visited {
+    /// Record a visit to an IFD, returning `None` if it was already visited or too many entries
+    /// have been visited in total
+    fn visit(&mut self, offset: usize, count: usize) -> Option<()> {
+        self.entries += count;
+        (self.offsets.insert(offset) && self.entries <= MAX_ENTRIES).then_some(())
+    }
+}
+
+/// TIFF structure of Exif data, where all offsets are relative to the start
+struct Tiff {
+    data: Vec<u8>,
+    big_endian: bool,
+}
+
+impl Tiff {
+    fn new(data: Vec<u8>) -> Option<Self> {
+        let big_endian = match data.get(0..4)? {
+            b"II*\0" => false,
+            b"MM\0*" => true,
+            _ => return None,
+        };
+        Some(Self { data, big_endian })
+    }
+
+    fn u16(&self, pos: usize) -> Option<u16> {
+        let bytes = self.data.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, pos: usize) -> Option<u32> {
+        let bytes = self.data.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn set_u16(&mut self, pos: usize, value: u16) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        self.data[pos..pos + 2].copy_from_slice(&bytes);
+    }
+
+    fn set_u32(&mut self, pos: usize, value: u32) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        self.data[pos..pos + 4].copy_from_slice(&bytes);
+    }
+
+    /// Get the range of the data, if it is within bounds
+    fn range(&self, start: usize, len: usize) -> Option<Range<usize>> {
+        let end = start.checked_add(len)?;
+        (end <= self.data.len()).then_some(start..end)
+    }
+
+    /// Get the range of the value of an IFD entry
+    fn value_range(&self, pos: usize) -> Option<Range<usize>> {
+        let type_size = match self.u16(pos + 2)? {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 | 13 => 4,
+            5 | 10 | 12 => 8,
+            // The size of an unknown type is unknown, so only its value field is treated as data
+            _ => return self.range(pos + 8, 4),
+        };
+        let len = (self.u32(pos + 4)? as usize).checked_mul(type_size)?;
+        if len <= 4 {
+            self.range(pos + 8, len)
+        } else {
+            self.range(self.u32(pos + 8)? as usize, len)
+        }
+    }
+
+    /// Get the values of an IFD entry containing unsigned integers
+    fn values(&self, pos: usize) -> Option<Vec<u32>> {
+        let range = self.value_range(pos)?;
+        match self.u16(pos + 2)? {
+            3 => range
+                .step_by(2)
+                .map(|i| self.u16(i).map(u32::from))
+                .collect(),
+            4 => range.step_by(4).map(|i| self.u32(i)).collect(),
+            _ => None,
+        }
+    }
+
+    /// Find the entry with a tag in an IFD
+    fn find_entry(&self, offset: usize, tag: u16) -> Option<usize> {
+        let count = self.u16(offset)? as usize;
+        (0..count)
+            .map(|i| offset + 2 + 12 * i)
+            .find(|&pos| self.u16(pos) == Some(tag))
+    }
+
+    /// Collect the ranges of all data belonging to an IFD, including nested and following IFDs
+    fn extents(
+        &self,
+        offset: usize,
+        out: &mut Vec<Range<usize>>,
+        visited: &mut Visited,
+        depth: usize,
+    ) -> Option<()> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let count = self.u16(offset)? as usize;
+        visited.visit(offset, count)?;
+        out.push(self.range(offset, 2 + 12 * count + 4)?);
+        for i in 0..count {
+            self.entry_extents(offset + 2 + 12 * i, out, visited, depth)?;
+        }
+        // Image data is referenced by a pair of entries for the offsets and lengths
+        for (offsets_tag, lengths_tag) in [
+            (JPEG_OFFSET, JPEG_LENGTH),
+            (STRIP_OFFSETS, STRIP_BYTE_COUNTS),
+        ] {
+            let offsets = self.find_entry(offset, offsets_tag);
+            let lengths = self.find_entry(offset, lengths_tag);
+            if let (Some(offsets), Some(lengths)) = (offsets, lengths) {
+                let offsets = self.values(offsets)?;
+                let lengths = self.values(lengths)?;
+                for (&start, &len) in offsets.iter().zip(&lengths) {
+                    out.push(self.range(start as usize, len as usize)?);
+                }
+            }
+        }
+        let next = self.u32(offset + 2 + 12 * count)? as usize;
+        if next != 0 {
+            self.extents(next, out, visited, depth + 1)?;
+        }
+        Some(())
+    }
+
+    /// Collect the ranges of the value of an IFD entry, including any IFD it points to
+    fn entry_extents(
+        &self,
+        pos: usize,
+        out: &mut Vec<Range<usize>>,
+        visited: &mut Visited,
+        depth: usize,
+    ) -> Option<()> {
+        out.push(self.value_range(pos)?);
+        if matches!(self.u16(pos)?, EXIF_IFD | GPS_IFD | INTEROP_IFD) {
+            self.extents(self.u32(pos + 8)? as usize, out, visited, depth + 1)?;
+        }
+        Some(())
+    }
+
+    /// Remove the entries of an IFD and its nested IFDs that belong to the groups, collecting the
+    /// ranges of their data
+    fn strip_ifd(
+        &mut self,
+        offset: usize,
+        groups: &IndexSet<ExifGroup>,
+        removed: &mut Vec<Range<usize>>,
+        visited: &mut Visited,
+        depth: usize,
+    ) -> Option<()> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let count = self.u16(offset)? as usize;
+        visited.visit(offset, count)?;
+        let table = self.range(offset, 2 + 12 * count + 4)?;
+        let mut kept = Vec::with_capacity(count);
+        for i in 0..count {
+            let pos = offset + 2 + 12 * i;
+            let remove = match self.u16(pos)? {
+                GPS_IFD => groups.contains(&ExifGroup::Gps),
+                tag if SERIAL_TAGS.contains(&tag) => groups.contains(&ExifGroup::Serial),
+                EXIF_IFD | INTEROP_IFD => {
+                    let nested = self.u32(pos + 8)? as usize;
+                    self.strip_ifd(nested, groups, removed, visited, depth + 1)?;
+                    false
+                }
+                _ => false,
+            };
+            if remove {
+                self.entry_extents(pos, removed, visited, depth)?;
+            } else {
+                kept.push(self.data[pos..pos + 12].to_vec());
+            }
+        }
+        if kept.len() == count {
+            return Some(());
+        }
+
+        // Rewrite the table without the removed entries, clearing the space left at the end
+        let next = self.u32(offset + 2 + 12 * count)?;
+        removed.push(table);
+        self.set_u16(offset, kept.len() as u16);
+        for (i, entry) in kept.iter().enumerate() {
+            let pos = offset + 2 + 12 * i;
+            self.data[pos..pos + 12].copy_from_slice(entry);
+        }
+        self.set_u32(offset + 2 + 12 * kept.len(), next);
+        Some(())
+    }
+}
@@ -1,6 +1,8 @@
-use std::{fmt, fmt::Display, mem::transmute};
+use std::{fmt, fmt::Display, mem::transmute, sync::Arc};
 
-use crate::error::PngError;
+use crate::{error::PngError, strategies::FilterStrategy};
+
+mod simd;
 
 /// Filtering strategy for use in [`Options`][crate::Options]
 #[repr(u8)]
@@ -18,6 +20,9 @@ pub enum RowFilter {
     Bigrams,
     BigEnt,
     Brute,
+    BruteModel,
+    // Search strategies
+    Genetic,
 }
 
 impl TryFrom<u8> for RowFilter {
@@ -45,16 +50,62 @@ impl Display for RowFilter {
                 Self::Bigrams => "Bigrams",
                 Self::BigEnt => "BigEnt",
                 Self::Brute => "Brute",
+                Self::BruteModel => "BruteModel",
+                Self::Genetic => "Genetic",
             },
             f,
         )
     }
 }
 
+/// A filter to be evaluated, either a [`RowFilter`] or a custom filter from the options
+#[derive(Debug, Clone)]
+pub(crate) enum Filter {
+    Row(RowFilter),
+    /// A custom strategy, along with its index in the options
+    Custom(usize, Arc<dyn FilterStrategy>),
+    /// A standard filter for each row
+    Rows(Arc<[RowFilter]>),
+    /// The filter for each row of the input file
+    Original(Arc<[RowFilter]>),
+}
+
+impl Filter {
+    /// Key for preferring one filter over another when results are otherwise equal
+    pub fn order(&self) -> usize {
+        match self {
+            Self::Row(filter) => *filter as usize,
+            Self::Custom(i, _) => RowFilter::LAST as usize + 1 + i,
+            Self::Rows(_) => usize::MAX - 1,
+            // Prefer our own filters if the result is the same
+            Self::Original(_) => usize::MAX,
+        }
+    }
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Row(filter) => Display::fmt(filter, f),
+            Self::Custom(_, strategy) => f.pad(strategy.name()),
+            Self::Rows(_) => f.pad("Rows"),
+            Self::Original(_) => f.pad("Original"),
+        }
+    }
+}
+
 impl RowFilter {
-    pub const LAST: u8 = Self::Brute as u8;
+    pub const LAST: u8 = Self::Genetic as u8;
     pub(crate) const STANDARD: [Self; 5] =
         [Self::None, Self::Sub, Self::Up, Self::Average, Self::Paeth];
+    pub(crate) const HEURISTIC: [Self; 6] = [
+        Self::MinSum,
+        Self::Entropy,
+        Self::Bigrams,
+        Self::BigEnt,
+        Self::Brute,
+        Self::BruteModel,
+    ];
     pub(crate) const SINGLE_LINE: [Self; 2] = [Self::None, Self::Sub];
 
     pub(crate) fn filter_line(
@@ -75,6 +126,13 @@ impl RowFilter {
         buf.clear();
         buf.reserve(data.len() + 1);
         buf.push(self as u8);
+        if !simd::filter_line(self, bpp, data, prev_line, buf) {
+            self.filter_line_scalar(bpp, data, prev_line, buf);
+        }
+    }
+
+    /// Apply a standard filter without SIMD, appending the result to `buf`
+    fn filter_line_scalar(self, bpp: usize, data: &[u8], prev_line: &[u8], buf: &mut Vec<u8>) {
         match self {
             Self::None => {
                 buf.extend_from_slice(data);
@@ -120,6 +178,7 @@ impl RowFilter {
     }
 
     // Optimize fully transparent pixels of a scanline such that they will be zeroed when filtered
+    #[allow(clippy::needless_range_loop)]
     fn optimize_alpha(self, bpp: usize, data: &mut [u8], prev_line: &[u8], color_bytes: usize) {
         if self == Self::None {
             // Assume transparent pixels already set to 0
@@ -188,6 +247,23 @@ impl RowFilter {
         buf.reserve(data.len());
         assert!(data.len() >= bpp);
         assert_eq!(data.len(), prev_line.len());
+        if self != Self::None
+            && self <= Self::Paeth
+            && simd::unfilter_line(self, bpp, data, prev_line, buf)
+        {
+            return Ok(());
+        }
+        self.unfilter_line_scalar(bpp, data, prev_line, buf)
+    }
+
+    /// Reverse a standard filter without SIMD, appending the result to `buf`
+    fn unfilter_line_scalar(
+        self,
+        bpp: usize,
+        data: &[u8],
+        prev_line: &[u8],
+        buf: &mut Vec<u8>,
+    ) -> Result<(), PngError> {
         match self {
             Self::None => {
                 buf.extend_from_slice(data);
@@ -238,6 +314,41 @@ impl RowFilter {
     }
 }
 
+/// Apply a standard filter to a line, with or without SIMD, for comparison in tests and benches
+pub fn filter_line(
+    filter: RowFilter,
+    bpp: usize,
+    data: &mut [u8],
+    prev_line: &[u8],
+    buf: &mut Vec<u8>,
+    scalar: bool,
+) {
+    if scalar {
+        buf.clear();
+        buf.push(filter as u8);
+        filter.filter_line_scalar(bpp, data, prev_line, buf);
+    } else {
+        filter.filter_line(bpp, data, prev_line, buf, 0);
+    }
+}
+
+/// Reverse a standard filter on a line, with or without SIMD, for comparison in tests and benches
+pub fn unfilter_line(
+    filter: RowFilter,
+    bpp: usize,
+    data: &[u8],
+    prev_line: &[u8],
+    buf: &mut Vec<u8>,
+    scalar: bool,
+) -> Result<(), PngError> {
+    if scalar {
+        buf.clear();
+        filter.unfilter_line_scalar(bpp, data, prev_line, buf)
+    } else {
+        filter.unfilter_line(bpp, data, prev_line, buf)
+    }
+}
+
 fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
     let p = i32::from(a) + i32::from(b) - i32::from(c);
     let pa = (p - i32::from(a)).abs();
@@ -0,0 +1,506 @@
+//! SIMD implementations of the standard filters
+//!
+//! Filtering has no dependency between the bytes of a line, so Average and Paeth are done 16 bytes
+//! at a time using SSE2 on x86_64 and NEON on aarch64. Unfiltering Sub, Average and Paeth depends
+//! on the previous pixel, so these are done one pixel at a time, for 3 and 4 byte pixels only.
+//! Both instruction sets are always available on their architectures, and AVX2 is also used for
+//! filtering 32 bytes at a time if it is detected at runtime. The compiler already
+//! vectorizes the scalar versions of filtering Sub and Up and unfiltering Up, which are as fast.
+//! Each function returns false if SIMD is not used, in which case the scalar version is used.
+
+#[cfg(target_arch = "aarch64")]
+use self::neon::{filter_bytes, unfilter_pixels};
+#[cfg(target_arch = "x86_64")]
+use self::x86::{filter_bytes, unfilter_pixels};
+use super::{paeth_predictor, RowFilter};
+
+/// Whether SIMD is supported on the target architecture
+const SIMD: bool = cfg!(any(target_arch = "x86_64", target_arch = "aarch64"));
+
+/// Apply a standard filter to the line, appending the result to `buf`
+pub fn filter_line(
+    filter: RowFilter,
+    bpp: usize,
+    data: &[u8],
+    prev_line: &[u8],
+    buf: &mut Vec<u8>,
+) -> bool {
+    if !SIMD || !matches!(filter, RowFilter::Average | RowFilter::Paeth) {
+        return false;
+    }
+    let start = buf.len();
+    buf.resize(start + data.len(), 0);
+    let out = &mut buf[start..];
+    // The first pixel has no left neighbor, so it is done separately
+    for (i, out) in out.iter_mut().enumerate().take(bpp) {
+        *out = filter_byte(filter, bpp, data, prev_line, i);
+    }
+    #[cfg(target_arch = "x86_64")]
+    let start = if is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 support has been checked
+        unsafe { x86::filter_bytes_avx2(filter, bpp, data, prev_line, out, bpp) }
+    } else {
+        bpp
+    };
+    #[cfg(not(target_arch = "x86_64"))]
+    let start = bpp;
+    // SAFETY: SSE2 and NEON are always available on their respective architectures
+    let end = unsafe { filter_bytes(filter, bpp, data, prev_line, out, start) };
+    for (i, out) in out.iter_mut().enumerate().skip(end) {
+        *out = filter_byte(filter, bpp, data, prev_line, i);
+    }
+    true
+}
+
+/// Reverse a standard filter on the line, appending the result to `buf`
+pub fn unfilter_line(
+    filter: RowFilter,
+    bpp: usize,
+    data: &[u8],
+    prev_line: &[u8],
+    buf: &mut Vec<u8>,
+) -> bool {
+    if !SIMD {
+        return false;
+    }
+    match filter {
+        RowFilter::Sub | RowFilter::Average | RowFilter::Paeth
+            if (bpp == 3 || bpp == 4) && data.len() % bpp == 0 =>
+        {
+            let start = buf.len();
+            buf.resize(start + data.len(), 0);
+            let out = &mut buf[start..];
+            // SAFETY: SSE2 and NEON are always available on their respective architectures
+            unsafe {
+                if bpp == 3 {
+                    unfilter_pixels::<3>(filter, data, prev_line, out);
+                } else {
+                    unfilter_pixels::<4>(filter, data, prev_line, out);
+                }
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Filter a single byte, for the parts of the line that don't fill a whole vector
+#[inline(always)]
+fn filter_byte(filter: RowFilter, bpp: usize, data: &[u8], prev_line: &[u8], i: usize) -> u8 {
+    let up = prev_line[i];
+    let (left, up_left) = match i.checked_sub(bpp) {
+        Some(x) => (data[x], prev_line[x]),
+        None => (0, 0),
+    };
+    let predictor = match filter {
+        RowFilter::Average => ((u16::from(left) + u16::from(up)) >> 1) as u8,
+        RowFilter::Paeth => paeth_predictor(left, up, up_left),
+        _ => unreachable!(),
+    };
+    data[i].wrapping_sub(predictor)
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+unsafe fn filter_bytes(
+    _: RowFilter,
+    _: usize,
+    _: &[u8],
+    _: &[u8],
+    _: &mut [u8],
+    _: usize,
+) -> usize {
+    unreachable!()
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+unsafe fn unfilter_pixels<const BPP: usize>(_: RowFilter, _: &[u8], _: &[u8], _: &mut [u8]) {
+    unreachable!()
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::RowFilter;
+
+    #[inline(always)]
+    unsafe fn load<const BPP: usize>(pixel: &[u8]) -> __m128i {
+        let mut bytes = [0; 4];
+        bytes[..BPP].copy_from_slice(pixel);
+        _mm_cvtsi32_si128(i32::from_le_bytes(bytes))
+    }
+
+    #[inline(always)]
+    unsafe fn store<const BPP: usize>(value: __m128i, out: &mut [u8]) {
+        out.copy_from_slice(&_mm_cvtsi128_si32(value).to_le_bytes()[..BPP]);
+    }
+
+    /// Load 16 bytes, which must be within bounds
+    #[inline(always)]
+    unsafe fn load_bytes(data: &[u8], i: usize) -> __m128i {
+        debug_assert!(i + 16 <= data.len());
+        _mm_loadu_si128(data.as_ptr().add(i).cast())
+    }
+
+    /// Store 16 bytes, which must be within bounds
+    #[inline(always)]
+    unsafe fn store_bytes(value: __m128i, out: &mut [u8], i: usize) {
+        debug_assert!(i + 16 <= out.len());
+        _mm_storeu_si128(out.as_mut_ptr().add(i).cast(), value);
+    }
+
+    #[inline(always)]
+    unsafe fn abs_epi16(x: __m128i) -> __m128i {
+        _mm_max_epi16(x, _mm_sub_epi16(_mm_setzero_si128(), x))
+    }
+
+    #[inline(always)]
+    unsafe fn select(mask: __m128i, a: __m128i, b: __m128i) -> __m128i {
+        _mm_or_si128(_mm_and_si128(mask, a), _mm_andnot_si128(mask, b))
+    }
+
+    /// Average of unsigned bytes, rounding down
+    #[inline(always)]
+    unsafe fn average(a: __m128i, b: __m128i) -> __m128i {
+        // The average instruction rounds up, so subtract the low bit of the sum
+        let one = _mm_set1_epi8(1);
+        _mm_sub_epi8(_mm_avg_epu8(a, b), _mm_and_si128(_mm_xor_si128(a, b), one))
+    }
+
+    /// Paeth predictor of 16-bit values
+    #[inline(always)]
+    unsafe fn paeth(a: __m128i, b: __m128i, c: __m128i) -> __m128i {
+        let pa = abs_epi16(_mm_sub_epi16(b, c));
+        let pb = abs_epi16(_mm_sub_epi16(a, c));
+        let pc = abs_epi16(_mm_sub_epi16(_mm_add_epi16(a, b), _mm_add_epi16(c, c)));
+        let smallest = _mm_min_epi16(pc, _mm_min_epi16(pa, pb));
+        // Ties are broken in the order a, b, c
+        let nearest = select(_mm_cmpeq_epi16(smallest, pb), b, c);
+        select(_mm_cmpeq_epi16(smallest, pa), a, nearest)
+    }
+
+    /// Filter 16 bytes at a time from `start`, returning the position where it stopped
+    ///
+    /// All slices must have the same length, and `start` must be at least `bpp`.
+    pub unsafe fn filter_bytes(
+        filter: RowFilter,
+        bpp: usize,
+        data: &[u8],
+        prev_line: &[u8],
+        out: &mut [u8],
+        start: usize,
+    ) -> usize {
+        let zero = _mm_setzero_si128();
+        // Each filter has its own loop, with the predictor inlined
+        match filter {
+            RowFilter::Average => filter_with(data, out, start, |i| {
+                average(load_bytes(data, i - bpp), load_bytes(prev_line, i))
+            }),
+            RowFilter::Paeth => filter_with(data, out, start, |i| {
+                // Work in 16 bits to avoid overflow
+                let a = load_bytes(data, i - bpp);
+                let b = load_bytes(prev_line, i);
+                let c = load_bytes(prev_line, i - bpp);
+                let lo = paeth(
+                    _mm_unpacklo_epi8(a, zero),
+                    _mm_unpacklo_epi8(b, zero),
+                    _mm_unpacklo_epi8(c, zero),
+                );
+                let hi = paeth(
+                    _mm_unpackhi_epi8(a, zero),
+                    _mm_unpackhi_epi8(b, zero),
+                    _mm_unpackhi_epi8(c, zero),
+                );
+                _mm_packus_epi16(lo, hi)
+            }),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Subtract the predictor from 16 bytes at a time, returning the position where it stopped
+    #[inline(always)]
+    unsafe fn filter_with(
+        data: &[u8],
+        out: &mut [u8],
+        start: usize,
+        predictor: impl Fn(usize) -> __m128i,
+    ) -> usize {
+        let mut i = start;
+        while i + 16 <= data.len() {
+            store_bytes(_mm_sub_epi8(load_bytes(data, i), predictor(i)), out, i);
+            i += 16;
+        }
+        i
+    }
+
+    /// Paeth predictor of 16-bit values, using AVX2
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn paeth_avx2(a: __m256i, b: __m256i, c: __m256i) -> __m256i {
+        let pa = _mm256_abs_epi16(_mm256_sub_epi16(b, c));
+        let pb = _mm256_abs_epi16(_mm256_sub_epi16(a, c));
+        let pc = _mm256_abs_epi16(_mm256_sub_epi16(
+            _mm256_add_epi16(a, b),
+            _mm256_add_epi16(c, c),
+        ));
+        let smallest = _mm256_min_epi16(pc, _mm256_min_epi16(pa, pb));
+        // Ties are broken in the order a, b, c
+        let nearest = _mm256_blendv_epi8(c, b, _mm256_cmpeq_epi16(smallest, pb));
+        _mm256_blendv_epi8(nearest, a, _mm256_cmpeq_epi16(smallest, pa))
+    }
+
+    /// Filter 32 bytes at a time from `start` using AVX2, returning the position where it stopped
+    ///
+    /// All slices must have the same length, and `start` must be at least `bpp`.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn filter_bytes_avx2(
+        filter: RowFilter,
+        bpp: usize,
+        data: &[u8],
+        prev_line: &[u8],
+        out: &mut [u8],
+        start: usize,
+    ) -> usize {
+        assert!(prev_line.len() == data.len() && out.len() == data.len() && start >= bpp);
+        let zero = _mm256_setzero_si256();
+        let one = _mm256_set1_epi8(1);
+        let mut i = start;
+        while i + 32 <= data.len() {
+            let a = _mm256_loadu_si256(data.as_ptr().add(i - bpp).cast());
+            let b = _mm256_loadu_si256(prev_line.as_ptr().add(i).cast());
+            let predictor = match filter {
+                RowFilter::Average => {
+                    // The average instruction rounds up, so subtract the low bit of the sum
+                    let rounding = _mm256_and_si256(_mm256_xor_si256(a, b), one);
+                    _mm256_sub_epi8(_mm256_avg_epu8(a, b), rounding)
+                }
+                RowFilter::Paeth => {
+                    // Work in 16 bits to avoid overflow, which unpacks and packs within each half
+                    let c = _mm256_loadu_si256(prev_line.as_ptr().add(i - bpp).cast());
+                    let lo = paeth_avx2(
+                        _mm256_unpacklo_epi8(a, zero),
+                        _mm256_unpacklo_epi8(b, zero),
+                        _mm256_unpacklo_epi8(c, zero),
+                    );
+                    let hi = paeth_avx2(
+                        _mm256_unpackhi_epi8(a, zero),
+                        _mm256_unpackhi_epi8(b, zero),
+                        _mm256_unpackhi_epi8(c, zero),
+                    );
+                    _mm256_packus_epi16(lo, hi)
+                }
+                _ => unreachable!(),
+            };
+            let x = _mm256_sub_epi8(_mm256_loadu_si256(data.as_ptr().add(i).cast()), predictor);
+            _mm256_storeu_si256(out.as_mut_ptr().add(i).cast(), x);
+            i += 32;
+        }
+        i
+    }
+
+    /// Unfilter Sub, Average or Paeth one pixel at a time, in the style of libpng
+    pub unsafe fn unfilter_pixels<const BPP: usize>(
+        filter: RowFilter,
+        data: &[u8],
+        prev_line: &[u8],
+        out: &mut [u8],
+    ) {
+        let zero = _mm_setzero_si128();
+        let pixels = out
+            .chunks_exact_mut(BPP)
+            .zip(data.chunks_exact(BPP).zip(prev_line.chunks_exact(BPP)));
+        match filter {
+            RowFilter::Sub => {
+                let mut a = zero;
+                for (out, (cur, _)) in pixels {
+                    a = _mm_add_epi8(a, load::<BPP>(cur));
+                    store::<BPP>(a, out);
+                }
+            }
+            RowFilter::Average => {
+                let mut a = zero;
+                for (out, (cur, up)) in pixels {
+                    a = _mm_add_epi8(average(a, load::<BPP>(up)), load::<BPP>(cur));
+                    store::<BPP>(a, out);
+                }
+            }
+            RowFilter::Paeth => {
+                // Work in 16 bits to avoid overflow
+                let mut a = zero;
+                let mut c = zero;
+                for (out, (cur, up)) in pixels {
+                    let b = _mm_unpacklo_epi8(load::<BPP>(up), zero);
+                    let nearest = paeth(a, b, c);
+                    let x = _mm_add_epi8(_mm_packus_epi16(nearest, nearest), load::<BPP>(cur));
+                    store::<BPP>(x, out);
+                    a = _mm_unpacklo_epi8(x, zero);
+                    c = b;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use super::RowFilter;
+
+    #[inline(always)]
+    unsafe fn load<const BPP: usize>(pixel: &[u8]) -> uint8x8_t {
+        let mut bytes = [0; 4];
+        bytes[..BPP].copy_from_slice(pixel);
+        vcreate_u8(u64::from(u32::from_le_bytes(bytes)))
+    }
+
+    #[inline(always)]
+    unsafe fn store<const BPP: usize>(value: uint8x8_t, out: &mut [u8]) {
+        let value = vget_lane_u32::<0>(vreinterpret_u32_u8(value));
+        out.copy_from_slice(&value.to_le_bytes()[..BPP]);
+    }
+
+    /// Load 16 bytes, which must be within bounds
+    #[inline(always)]
+    unsafe fn load_bytes(data: &[u8], i: usize) -> uint8x16_t {
+        debug_assert!(i + 16 <= data.len());
+        vld1q_u8(data.as_ptr().add(i))
+    }
+
+    /// Store 16 bytes, which must be within bounds
+    #[inline(always)]
+    unsafe fn store_bytes(value: uint8x16_t, out: &mut [u8], i: usize) {
+        debug_assert!(i + 16 <= out.len());
+        vst1q_u8(out.as_mut_ptr().add(i), value);
+    }
+
+    #[inline(always)]
+    unsafe fn widen(x: uint8x8_t) -> int16x4_t {
+        vreinterpret_s16_u16(vget_low_u16(vmovl_u8(x)))
+    }
+
+    #[inline(always)]
+    unsafe fn narrow(x: int16x4_t) -> uint8x8_t {
+        vmovn_u16(vcombine_u16(vreinterpret_u16_s16(x), vdup_n_u16(0)))
+    }
+
+    /// Paeth predictor of 16-bit values
+    #[inline(always)]
+    unsafe fn paeth(a: int16x8_t, b: int16x8_t, c: int16x8_t) -> int16x8_t {
+        let pa = vabdq_s16(b, c);
+        let pb = vabdq_s16(a, c);
+        let pc = vabdq_s16(vaddq_s16(a, b), vaddq_s16(c, c));
+        let smallest = vminq_s16(pc, vminq_s16(pa, pb));
+        // Ties are broken in the order a, b, c
+        let nearest = vbslq_s16(vceqq_s16(smallest, pb), b, c);
+        vbslq_s16(vceqq_s16(smallest, pa), a, nearest)
+    }
+
+    /// Filter 16 bytes at a time from `start`, returning the position where it stopped
+    ///
+    /// All slices must have the same length, and `start` must be at least `bpp`.
+    pub unsafe fn filter_bytes(
+        filter: RowFilter,
+        bpp: usize,
+        data: &[u8],
+        prev_line: &[u8],
+        out: &mut [u8],
+        start: usize,
+    ) -> usize {
+        let wide = |x: uint8x8_t| vreinterpretq_s16_u16(vmovl_u8(x));
+        // Each filter has its own loop, with the predictor inlined
+        match filter {
+            RowFilter::Average => filter_with(data, out, start, |i| {
+                // Halving add rounds down, as required
+                vhaddq_u8(load_bytes(data, i - bpp), load_bytes(prev_line, i))
+            }),
+            RowFilter::Paeth => filter_with(data, out, start, |i| {
+                // Work in 16 bits to avoid overflow
+                let a = load_bytes(data, i - bpp);
+                let b = load_bytes(prev_line, i);
+                let c = load_bytes(prev_line, i - bpp);
+                let lo = paeth(
+                    wide(vget_low_u8(a)),
+                    wide(vget_low_u8(b)),
+                    wide(vget_low_u8(c)),
+                );
+                let hi = paeth(
+                    wide(vget_high_u8(a)),
+                    wide(vget_high_u8(b)),
+                    wide(vget_high_u8(c)),
+                );
+                vcombine_u8(
+                    vmovn_u16(vreinterpretq_u16_s16(lo)),
+                    vmovn_u16(vreinterpretq_u16_s16(hi)),
+                )
+            }),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Subtract the predictor from 16 bytes at a time, returning the position where it stopped
+    #[inline(always)]
+    unsafe fn filter_with(
+        data: &[u8],
+        out: &mut [u8],
+        start: usize,
+        predictor: impl Fn(usize) -> uint8x16_t,
+    ) -> usize {
+        let mut i = start;
+        while i + 16 <= data.len() {
+            store_bytes(vsubq_u8(load_bytes(data, i), predictor(i)), out, i);
+            i += 16;
+        }
+        i
+    }
+
+    /// Unfilter Sub, Average or Paeth one pixel at a time, in the style of libpng
+    pub unsafe fn unfilter_pixels<const BPP: usize>(
+        filter: RowFilter,
+        data: &[u8],
+        prev_line: &[u8],
+        out: &mut [u8],
+    ) {
+        let pixels = out
+            .chunks_exact_mut(BPP)
+            .zip(data.chunks_exact(BPP).zip(prev_line.chunks_exact(BPP)));
+        match filter {
+            RowFilter::Sub => {
+                let mut a = vdup_n_u8(0);
+                for (out, (cur, _)) in pixels {
+                    a = vadd_u8(a, load::<BPP>(cur));
+                    store::<BPP>(a, out);
+                }
+            }
+            RowFilter::Average => {
+                let mut a = vdup_n_u8(0);
+                for (out, (cur, up)) in pixels {
+                    // Halving add rounds down, as required
+                    a = vadd_u8(vhadd_u8(a, load::<BPP>(up)), load::<BPP>(cur));
+                    store::<BPP>(a, out);
+                }
+            }
+            RowFilter::Paeth => {
+                // Work in 16 bits to avoid overflow
+                let mut a = vdup_n_s16(0);
+                let mut c = vdup_n_s16(0);
+                for (out, (cur, up)) in pixels {
+                    let b = widen(load::<BPP>(up));
+                    let pa = vabd_s16(b, c);
+                    let pb = vabd_s16(a, c);
+                    let pc = vabd_s16(vadd_s16(a, b), vadd_s16(c, c));
+                    let smallest = vmin_s16(pc, vmin_s16(pa, pb));
+                    // Ties are broken in the order a, b, c
+                    let nearest = vbsl_s16(vceq_s16(smallest, pb), b, c);
+                    let nearest = vbsl_s16(vceq_s16(smallest, pa), a, nearest);
+                    let x = vadd_u8(narrow(nearest), load::<BPP>(cur));
+                    store::<BPP>(x, out);
+                    a = widen(x);
+                    c = b;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
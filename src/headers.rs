@@ -7,7 +7,10 @@ use crate::{
     deflate::{crc32, inflate},
     display_chunks::DISPLAY_CHUNKS,
     error::PngError,
-    interlace::Interlacing,
+    exif::strip_exif,
+    interlace::{InterlaceMode, Interlacing},
+    metadata::{text_keyword, ChunkMetadata, MetadataEdit},
+    xmp::{strip_xmp, XMP_KEYWORD},
     Deflaters, Options, PngResult,
 };
 
@@ -63,6 +66,31 @@ impl IhdrData {
             size + bitmap_size(bpp, w, h >> 1) + (h >> 1)
         }
     }
+
+    /// Number of scan lines, including those of each interlacing pass
+    #[must_use]
+    pub fn num_lines(&self) -> usize {
+        let w = self.width as usize;
+        let h = self.height as usize;
+
+        if self.interlaced == Interlacing::None {
+            h
+        } else {
+            let mut lines = (h + 7) >> 3;
+            if w > 4 {
+                lines += (h + 7) >> 3;
+            }
+            lines += (h + 3) >> 3;
+            if w > 2 {
+                lines += (h + 3) >> 2;
+            }
+            lines += (h + 1) >> 2;
+            if w > 1 {
+                lines += (h + 1) >> 1;
+            }
+            lines + (h >> 1)
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -98,6 +126,51 @@ impl StripChunks {
             Self::All => false,
         }
     }
+
+    /// Whether colorspace chunks may be replaced with an sRGB chunk
+    fn may_replace_with_srgb(&self) -> bool {
+        *self != Self::None && self.keep(b"sRGB")
+    }
+}
+
+/// [`Options`][crate::Options] to use when stripping text chunks by keyword
+///
+/// This applies to tEXt, zTXt and iTXt chunks, and takes precedence over [`StripChunks`] for any
+/// keywords that are listed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum StripText {
+    /// None
+    None,
+    /// Remove text chunks with these keywords
+    Strip(IndexSet<String>),
+    /// Remove all text chunks except those with these keywords
+    Keep(IndexSet<String>),
+}
+
+impl StripText {
+    /// Whether to keep a text chunk with the keyword, or `None` if there is no rule for it
+    fn keep(&self, keyword: &str) -> Option<bool> {
+        match &self {
+            Self::None => None,
+            Self::Strip(keywords) => keywords.contains(keyword).then_some(false),
+            Self::Keep(keywords) => Some(keywords.contains(keyword)),
+        }
+    }
+}
+
+/// Whether to keep a chunk, according to the options for stripping chunks and text
+///
+/// gAMA and cHRM chunks are kept if they may be replaced with an sRGB chunk, as that depends on
+/// both of them. Any that are not replaced are stripped in [`preprocess_chunks`].
+pub fn keep_chunk(name: &[u8; 4], data: &[u8], opts: &Options) -> bool {
+    text_keyword(name, data)
+        .and_then(|keyword| opts.strip_text.keep(&keyword))
+        .unwrap_or_else(|| opts.strip.keep(name) || is_srgb_candidate(name, opts))
+}
+
+/// Whether a chunk is only kept until it is checked for replacement with an sRGB chunk
+fn is_srgb_candidate(name: &[u8; 4], opts: &Options) -> bool {
+    matches!(name, b"gAMA" | b"cHRM") && opts.strip.may_replace_with_srgb()
 }
 
 #[inline]
@@ -315,24 +388,195 @@ pub fn srgb_rendering_intent(icc_data: &[u8]) -> Option<u8> {
     }
 }
 
+/// Check whether gAMA and cHRM chunks describe the sRGB colorspace
+///
+/// The values must be within 0.001 of those recommended by the PNG spec for sRGB images, which
+/// is the same tolerance used by libpng.
+#[must_use]
+pub fn is_srgb_gamma_chrm(gama: &[u8], chrm: &[u8]) -> bool {
+    const SRGB_GAMMA: u32 = 45455;
+    const SRGB_CHRM: [u32; 8] = [31270, 32900, 64000, 33000, 30000, 60000, 15000, 6000];
+    const TOLERANCE: u32 = 100;
+
+    gama.len() == 4
+        && chrm.len() == 32
+        && read_be_u32(gama).abs_diff(SRGB_GAMMA) <= TOLERANCE
+        && chrm
+            .chunks_exact(4)
+            .zip(SRGB_CHRM)
+            .all(|(value, expected)| read_be_u32(value).abs_diff(expected) <= TOLERANCE)
+}
+
+/// Derive an equivalent grayscale ICC profile from an RGB matrix/TRC profile
+///
+/// This is only possible if the red, green and blue curves are identical and the colorants add up
+/// to the PCS illuminant, such that neutral colors will be rendered the same in either profile.
+pub fn gray_icc_profile(icc_data: &[u8]) -> Option<Vec<u8>> {
+    const HEADER_SIZE: usize = 128;
+    // Tags that are still relevant to a grayscale profile
+    const KEEP_TAGS: [&[u8; 4]; 5] = [b"desc", b"cprt", b"wtpt", b"bkpt", b"chad"];
+
+    let header = icc_data.get(..HEADER_SIZE)?;
+    if &header[16..20] != b"RGB " || &header[20..24] != b"XYZ " {
+        return None;
+    }
+    let tag_count = read_be_u32(icc_data.get(HEADER_SIZE..HEADER_SIZE + 4)?) as usize;
+    let table_end = HEADER_SIZE + 4 + tag_count.checked_mul(12)?;
+    let mut tags = Vec::with_capacity(tag_count);
+    for entry in icc_data.get(HEADER_SIZE + 4..table_end)?.chunks_exact(12) {
+        let offset = read_be_u32(&entry[4..8]) as usize;
+        let size = read_be_u32(&entry[8..12]) as usize;
+        tags.push((
+            &entry[0..4],
+            icc_data.get(offset..offset.checked_add(size)?)?,
+        ));
+    }
+    let tag = |sig: &[u8; 4]| tags.iter().find(|(s, _)| s == sig).map(|(_, d)| *d);
+
+    // LUT-based transforms take precedence over the matrix/TRC model, so we can't convert these
+    if tags
+        .iter()
+        .any(|(s, _)| matches!(&s[..3], b"A2B" | b"B2A" | b"D2B" | b"B2D"))
+    {
+        return None;
+    }
+    let trc = tag(b"rTRC")?;
+    if tag(b"gTRC")? != trc || tag(b"bTRC")? != trc || tag(b"wtpt").is_none() {
+        return None;
+    }
+    // The sum of the colorants must match the illuminant, allowing for some rounding error
+    let mut sum = [0i64; 3];
+    for sig in [b"rXYZ", b"gXYZ", b"bXYZ"] {
+        let xyz = tag(sig).filter(|d| d.len() >= 20 && &d[0..4] == b"XYZ ")?;
+        for (c, v) in sum.iter_mut().zip(xyz[8..20].chunks_exact(4)) {
+            *c += i64::from(read_be_u32(v) as i32);
+        }
+    }
+    let illuminant = header[68..80]
+        .chunks_exact(4)
+        .map(|v| i64::from(read_be_u32(v) as i32));
+    if sum.iter().zip(illuminant).any(|(s, i)| (s - i).abs() > 64) {
+        return None;
+    }
+
+    let mut new_tags = vec![(b"kTRC", trc)];
+    new_tags.extend(KEEP_TAGS.iter().filter_map(|sig| Some((*sig, tag(sig)?))));
+
+    let data_start = HEADER_SIZE + 4 + new_tags.len() * 12;
+    let mut table = Vec::with_capacity(data_start);
+    table.extend_from_slice(header);
+    table[16..20].copy_from_slice(b"GRAY");
+    // Clear the profile ID as it is no longer valid
+    table[84..100].fill(0);
+    table.extend_from_slice(&(new_tags.len() as u32).to_be_bytes());
+    let mut data = Vec::new();
+    for (sig, tag_data) in new_tags {
+        table.extend_from_slice(sig);
+        table.extend_from_slice(&((data_start + data.len()) as u32).to_be_bytes());
+        table.extend_from_slice(&(tag_data.len() as u32).to_be_bytes());
+        data.extend_from_slice(tag_data);
+        // Tag data must be aligned to 4 bytes
+        data.resize(data.len().next_multiple_of(4), 0);
+    }
+    table.append(&mut data);
+    let size = table.len() as u32;
+    table[0..4].copy_from_slice(&size.to_be_bytes());
+    Some(table)
+}
+
+/// Chunks describing HDR content, which must come before PLTE and IDAT
+const HDR_CHUNKS: [[u8; 4]; 3] = [*b"cICP", *b"mDCV", *b"cLLI"];
+
 /// Process aux chunks and potentially adjust options before optimizing
 pub fn preprocess_chunks(aux_chunks: &mut Vec<Chunk>, opts: &mut Options) {
+    if !opts.strip_exif.is_empty() {
+        aux_chunks.retain_mut(|chunk| {
+            if &chunk.name != b"eXIf" {
+                return true;
+            }
+            match strip_exif(&chunk.data, &opts.strip_exif) {
+                Some(data) if data != chunk.data => {
+                    debug!(
+                        "Stripped Exif tags: {} ({} bytes decrease)",
+                        data.len(),
+                        chunk.data.len() - data.len()
+                    );
+                    chunk.data = data;
+                }
+                Some(_) => {}
+                None => {
+                    // The tags may still be present, so the data can't be kept
+                    warn!("Failed to parse eXIf chunk, removing it");
+                    return false;
+                }
+            }
+            true
+        });
+    }
+
+    if !opts.strip_xmp.is_empty() {
+        for chunk in aux_chunks.iter_mut().filter(|c| &c.name == b"iTXt") {
+            let Some(ChunkMetadata::InternationalText {
+                keyword,
+                language,
+                translated_keyword,
+                text,
+                ..
+            }) = ChunkMetadata::from_chunk(&chunk.name, &chunk.data)
+            else {
+                continue;
+            };
+            if keyword != XMP_KEYWORD {
+                continue;
+            }
+            let Some(stripped) = strip_xmp(&text, &opts.strip_xmp) else {
+                warn!("Failed to parse XMP data, unable to strip XMP properties");
+                continue;
+            };
+            // The chunk is written back in whichever form is smaller
+            let xmp = |compressed| ChunkMetadata::InternationalText {
+                keyword: keyword.clone(),
+                language: language.clone(),
+                translated_keyword: translated_keyword.clone(),
+                text: stripped.clone(),
+                compressed,
+            };
+            let Ok(uncompressed) = xmp(false).to_chunk(opts.deflate) else {
+                continue;
+            };
+            let best = match xmp(true).to_chunk(opts.deflate) {
+                Ok(compressed) if compressed.data.len() < uncompressed.data.len() => compressed,
+                _ => uncompressed,
+            };
+            if stripped != text || best.data.len() < chunk.data.len() {
+                debug!(
+                    "Stripped XMP properties: {} ({} bytes decrease)",
+                    best.data.len(),
+                    chunk.data.len().saturating_sub(best.data.len())
+                );
+                *chunk = best;
+            }
+        }
+    }
+
     let has_srgb = aux_chunks.iter().any(|c| &c.name == b"sRGB");
-    // Grayscale conversion should not be performed if the image is not in the sRGB colorspace
-    // An sRGB profile would need to be stripped on conversion, so disallow if stripping is disabled
-    let mut allow_grayscale = !has_srgb || opts.strip != StripChunks::None;
+    // The colorspace chunks may only be replaced with an sRGB chunk when stripping is enabled
+    let may_replace_with_srgb = opts.strip.may_replace_with_srgb();
+    // Grayscale conversion should not be performed if the image is not in the sRGB colorspace,
+    // unless the profile can be converted to an equivalent grayscale profile
+    // An sRGB chunk is valid for all color types and can remain as is
+    let mut allow_grayscale = true;
 
     if let Some(iccp_idx) = aux_chunks.iter().position(|c| &c.name == b"iCCP") {
         allow_grayscale = false;
         // See if we can replace an iCCP chunk with an sRGB chunk
-        let may_replace_iccp = opts.strip != StripChunks::None && opts.strip.keep(b"sRGB");
-        if may_replace_iccp && has_srgb {
+        if may_replace_with_srgb && has_srgb {
             // Files aren't supposed to have both chunks, so we chose to honor sRGB
             trace!("Removing iCCP chunk due to conflict with sRGB chunk");
             aux_chunks.remove(iccp_idx);
             allow_grayscale = true;
         } else if let Some(icc) = extract_icc(&aux_chunks[iccp_idx]) {
-            let intent = if may_replace_iccp {
+            let intent = if may_replace_with_srgb {
                 srgb_rendering_intent(&icc)
             } else {
                 None
@@ -345,39 +589,113 @@ pub fn preprocess_chunks(aux_chunks: &mut Vec<Chunk>, opts: &mut Options) {
                     data: vec![intent],
                 };
                 allow_grayscale = true;
-            } else if opts.idat_recoding {
-                // Try recompressing the profile
-                let cur_len = aux_chunks[iccp_idx].data.len();
-                if let Ok(iccp) = make_iccp(&icc, opts.deflate, Some(cur_len - 1)) {
-                    debug!(
-                        "Recompressed iCCP chunk: {} ({} bytes decrease)",
-                        iccp.data.len(),
-                        cur_len - iccp.data.len()
-                    );
-                    aux_chunks[iccp_idx] = iccp;
+            } else {
+                // The profile will be converted in postprocessing if the image becomes grayscale
+                allow_grayscale = gray_icc_profile(&icc).is_some();
+                if opts.idat_recoding {
+                    // Try recompressing the profile
+                    let cur_len = aux_chunks[iccp_idx].data.len();
+                    if let Ok(iccp) = make_iccp(&icc, opts.deflate, Some(cur_len - 1)) {
+                        debug!(
+                            "Recompressed iCCP chunk: {} ({} bytes decrease)",
+                            iccp.data.len(),
+                            cur_len - iccp.data.len()
+                        );
+                        aux_chunks[iccp_idx] = iccp;
+                    }
                 }
             }
         }
     }
 
+    // See if we can replace gAMA and cHRM chunks with an sRGB chunk
+    // These are overridden by an iCCP chunk, in which case they are left as is
+    // Grayscale conversion remains allowed, as these only depend on the white point for grayscale
+    let gama_idx = aux_chunks.iter().position(|c| &c.name == b"gAMA");
+    let chrm_idx = aux_chunks.iter().position(|c| &c.name == b"cHRM");
+    if may_replace_with_srgb && (gama_idx.is_some() || chrm_idx.is_some()) {
+        if aux_chunks.iter().any(|c| &c.name == b"sRGB") {
+            // Files may include these for decoders that don't support sRGB, but they're obsolete
+            trace!("Removing gAMA and cHRM chunks as they are overridden by sRGB chunk");
+            aux_chunks.retain(|c| &c.name != b"gAMA" && &c.name != b"cHRM");
+        } else if let (Some(gama_idx), Some(chrm_idx)) = (gama_idx, chrm_idx) {
+            let has_iccp = aux_chunks.iter().any(|c| &c.name == b"iCCP");
+            if !has_iccp
+                && is_srgb_gamma_chrm(&aux_chunks[gama_idx].data, &aux_chunks[chrm_idx].data)
+            {
+                // The rendering intent is unknown, so use perceptual as is typical
+                trace!("Replacing gAMA and cHRM chunks with equivalent sRGB chunk");
+                aux_chunks[gama_idx] = Chunk {
+                    name: *b"sRGB",
+                    data: vec![0],
+                };
+                aux_chunks.remove(chrm_idx);
+            }
+        }
+    }
+    // Strip the remaining gAMA and cHRM chunks that were only kept for the check above, unless
+    // they were added by a metadata edit
+    let edited = |name: &[u8; 4]| {
+        opts.metadata.iter().any(|edit| match edit {
+            MetadataEdit::Insert(m) | MetadataEdit::Replace(m) => m.chunk_name() == *name,
+            _ => false,
+        })
+    };
+    aux_chunks.retain(|c| {
+        !is_srgb_candidate(&c.name, opts) || opts.strip.keep(&c.name) || edited(&c.name)
+    });
+
     if !allow_grayscale && opts.grayscale_reduction {
-        debug!("Disabling grayscale reduction due to presence of sRGB or iCCP chunk");
+        debug!("Disabling grayscale reduction due to presence of iCCP chunk");
         opts.grayscale_reduction = false;
     }
 
+    // The HDR chunks must come before the image data, so move any that don't
+    if let Some(mut idat_idx) = aux_chunks.iter().position(|c| &c.name == b"IDAT") {
+        let mut i = idat_idx + 1;
+        while i < aux_chunks.len() {
+            if HDR_CHUNKS.contains(&aux_chunks[i].name) {
+                warn!(
+                    "Moving {} chunk before IDAT",
+                    std::str::from_utf8(&aux_chunks[i].name).unwrap()
+                );
+                let chunk = aux_chunks.remove(i);
+                aux_chunks.insert(idat_idx, chunk);
+                idat_idx += 1;
+            }
+            i += 1;
+        }
+    }
+
+    if let Some(cicp) = aux_chunks.iter().find(|c| &c.name == b"cICP") {
+        // The code points are defined for grayscale images, but decoders don't reliably apply
+        // them to anything other than RGB, so the color type is kept
+        if opts.grayscale_reduction {
+            debug!("Disabling grayscale reduction due to presence of cICP chunk");
+            opts.grayscale_reduction = false;
+        }
+        // Scaling PQ or HLG content to 8 bits would cause visible banding
+        if opts.scale_16 && matches!(cicp.data.get(1), Some(16 | 18)) {
+            warn!("Disabling 16-bit scaling due to HDR transfer function in cICP chunk");
+            opts.scale_16 = false;
+        }
+    }
+
     // Check for APNG by presence of acTL chunk
+    // Other reductions are applied to all frames together, see `reduce_frames`
     if aux_chunks.iter().any(|c| &c.name == b"acTL") {
-        warn!("APNG detected, disabling all reductions");
-        opts.interlace = None;
-        opts.bit_depth_reduction = false;
-        opts.color_type_reduction = false;
-        opts.palette_reduction = false;
-        opts.grayscale_reduction = false;
+        debug!("APNG detected, disabling interlacing changes");
+        opts.interlace = InterlaceMode::Keep;
     }
 }
 
 /// Perform cleanup of certain aux chunks after optimization has been completed
-pub fn postprocess_chunks(aux_chunks: &mut Vec<Chunk>, ihdr: &IhdrData, orig_ihdr: &IhdrData) {
+pub fn postprocess_chunks(
+    aux_chunks: &mut Vec<Chunk>,
+    ihdr: &IhdrData,
+    orig_ihdr: &IhdrData,
+    deflater: Deflaters,
+) {
     // If the depth/color type has changed, some chunks may be invalid and should be dropped
     // While these could potentially be converted, they have no known use case today and are
     // generally more trouble than they're worth
@@ -394,17 +712,24 @@ pub fn postprocess_chunks(aux_chunks: &mut Vec<Chunk>, ihdr: &IhdrData, orig_ihd
         });
     }
 
-    // Remove any sRGB or iCCP chunks if the image was converted to or from grayscale
+    // If the image was converted to or from grayscale, an iCCP chunk must be converted or removed
+    // The sRGB chunk is valid for all color types and does not need to change
     if orig_ihdr.color_type.is_gray() != ihdr.color_type.is_gray() {
-        aux_chunks.retain(|c| {
-            let invalid = &c.name == b"sRGB" || &c.name == b"iCCP";
-            if invalid {
-                trace!(
-                    "Removing {} chunk as it no longer matches the color type",
-                    std::str::from_utf8(&c.name).unwrap()
-                );
+        if let Some(iccp_idx) = aux_chunks.iter().position(|c| &c.name == b"iCCP") {
+            let gray_iccp = if ihdr.color_type.is_gray() {
+                extract_icc(&aux_chunks[iccp_idx])
+                    .and_then(|icc| gray_icc_profile(&icc))
+                    .and_then(|icc| make_iccp(&icc, deflater, None).ok())
+            } else {
+                None
+            };
+            if let Some(iccp) = gray_iccp {
+                trace!("Replacing iCCP chunk with equivalent grayscale profile");
+                aux_chunks[iccp_idx] = iccp;
+            } else {
+                trace!("Removing iCCP chunk as it no longer matches the color type");
+                aux_chunks.remove(iccp_idx);
             }
-            !invalid
-        });
+        }
     }
 }
@@ -14,6 +14,21 @@ pub enum Interlacing {
     Adam7,
 }
 
+/// How to change the interlacing of the output, for use in [`Options`][crate::Options]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InterlaceMode {
+    /// Keep the interlacing type of the input
+    Keep,
+    /// Change the interlacing to the given type
+    Set(Interlacing),
+    /// Evaluate both interlacing types and keep whichever is smaller
+    Auto {
+        /// A percentage of the non-interlaced size, within which the interlaced version will be
+        /// preferred. Use `0.0` to simply keep the smaller version.
+        tolerance: f32,
+    },
+}
+
 impl TryFrom<u8> for Interlacing {
     type Error = PngError;
 
@@ -42,19 +42,26 @@ use rayon::prelude::*;
 pub use rgb::{RGB16, RGBA8};
 
 pub use crate::{
+    apng::{BlendOp, DisposeOp, FrameControl},
     colors::{BitDepth, ColorType},
     deflate::Deflaters,
     error::PngError,
+    exif::ExifGroup,
     filters::RowFilter,
-    headers::StripChunks,
-    interlace::Interlacing,
+    headers::{StripChunks, StripText},
+    interlace::{InterlaceMode, Interlacing},
+    metadata::{is_valid_keyword, read_metadata, ChunkMetadata, MetadataEdit, PhysUnit},
     options::{InFile, Options, OutFile},
+    reduction::alpha::AlphaFill,
+    strategies::{FilterStrategy, LineContext},
 };
 use crate::{
     evaluate::{Candidate, Evaluator},
+    filters::Filter,
     headers::*,
-    png::{PngData, PngImage},
-    reduction::*,
+    metadata::apply_metadata_edits,
+    png::{ModelSettings, PngData, PngImage},
+    reduction::{alpha::filled_alpha_channel, *},
 };
 
 mod apng;
@@ -64,21 +71,31 @@ mod deflate;
 mod display_chunks;
 mod error;
 mod evaluate;
+mod exif;
 mod filters;
 mod headers;
 mod interlace;
+mod metadata;
 mod options;
 mod png;
 mod reduction;
+mod rng;
 #[cfg(feature = "sanity-checks")]
 mod sanity_checks;
+mod strategies;
+mod xmp;
 
 /// Private to oxipng; don't use outside tests and benches
 #[doc(hidden)]
 pub mod internal_tests {
     #[cfg(feature = "sanity-checks")]
     pub use crate::sanity_checks::*;
-    pub use crate::{deflate::*, png::*, reduction::*};
+    pub use crate::{
+        deflate::*,
+        filters::{filter_line, unfilter_line},
+        png::*,
+        reduction::*,
+    };
 }
 
 pub type PngResult<T> = Result<T, PngError>;
@@ -88,6 +105,11 @@ pub type PngResult<T> = Result<T, PngError>;
 pub struct RawImage {
     png: Arc<PngImage>,
     aux_chunks: Vec<Chunk>,
+    /// Control of the image itself, as the first frame of an animation
+    frame_control: FrameControl,
+    /// Additional frames of an animation
+    frames: Vec<(Arc<PngImage>, FrameControl)>,
+    num_plays: u32,
 }
 
 impl RawImage {
@@ -135,6 +157,9 @@ impl RawImage {
                 data,
             }),
             aux_chunks: Vec::new(),
+            frame_control: FrameControl::default(),
+            frames: Vec::new(),
+            num_plays: 0,
         })
     }
 
@@ -152,32 +177,127 @@ impl RawImage {
         }
     }
 
+    /// Add a frame to create an animated png, where the image itself is the first frame
+    ///
+    /// * `width` - The width of the frame in pixels
+    /// * `height` - The height of the frame in pixels
+    /// * `data` - The raw pixel data of the frame, in the color type and bit depth of the image
+    /// * `control` - The position, delay and operations of the frame
+    pub fn add_frame(
+        &mut self,
+        width: u32,
+        height: u32,
+        data: Vec<u8>,
+        control: FrameControl,
+    ) -> Result<(), PngError> {
+        let ihdr = &self.png.ihdr;
+        if width == 0
+            || height == 0
+            || u64::from(control.x_offset) + u64::from(width) > u64::from(ihdr.width)
+            || u64::from(control.y_offset) + u64::from(height) > u64::from(ihdr.height)
+        {
+            return Err(PngError::new(
+                "Frame is empty or outside the bounds of the image",
+            ));
+        }
+        let frame = Self::new(width, height, ihdr.color_type.clone(), ihdr.bit_depth, data)?;
+        self.frames.push((frame.png, control));
+        Ok(())
+    }
+
+    /// Set the delay and operations of the image itself, as the first frame of an animation
+    ///
+    /// The image always covers the whole canvas, so the offsets must be zero.
+    pub fn set_frame_control(&mut self, control: FrameControl) -> Result<(), PngError> {
+        if control.x_offset != 0 || control.y_offset != 0 {
+            return Err(PngError::new("The first frame cannot have an offset"));
+        }
+        self.frame_control = control;
+        Ok(())
+    }
+
+    /// Set the number of times an animation plays, where 0 is infinite
+    pub fn set_num_plays(&mut self, num_plays: u32) {
+        self.num_plays = num_plays;
+    }
+
     /// Create an optimized png from the raw image data using the options provided
+    ///
+    /// If frames have been added, this will be an animated png.
     pub fn create_optimized_png(&self, opts: &Options) -> PngResult<Vec<u8>> {
         let mut opts = opts.to_owned();
         let mut aux_chunks: Vec<_> = self
             .aux_chunks
             .iter()
-            .filter(|c| opts.strip.keep(&c.name))
+            .filter(|c| keep_chunk(&c.name, &c.data, &opts))
             .cloned()
             .collect();
+        if !self.frames.is_empty() {
+            return self.create_optimized_apng(aux_chunks, &opts);
+        }
+        apply_metadata_edits(&mut aux_chunks, &opts.metadata, opts.deflate)?;
         preprocess_chunks(&mut aux_chunks, &mut opts);
+        validate_row_filters(&self.png.ihdr, &opts)?;
 
         let deadline = Arc::new(Deadline::new(opts.timeout));
-        let Some(result) = optimize_raw(self.png.clone(), &opts, deadline, None) else {
+        let Some(result) = optimize_raw(self.png.clone(), &opts, deadline, None, &[]) else {
             return Err(PngError::new("Failed to optimize input data"));
         };
 
         let mut png = PngData {
             raw: result.image,
             idat_data: result.data,
+            filters: Vec::new(),
             aux_chunks,
             frames: Vec::new(),
         };
-        postprocess_chunks(&mut png.aux_chunks, &png.raw.ihdr, &self.png.ihdr);
+        postprocess_chunks(
+            &mut png.aux_chunks,
+            &png.raw.ihdr,
+            &self.png.ihdr,
+            opts.deflate,
+        );
 
         Ok(png.output())
     }
+
+    /// Create an optimized animated png, from an initial encoding of all frames
+    fn create_optimized_apng(
+        &self,
+        mut aux_chunks: Vec<Chunk>,
+        opts: &Options,
+    ) -> PngResult<Vec<u8>> {
+        let num_frames = self.frames.len() as u32 + 1;
+        let actl = [num_frames.to_be_bytes(), self.num_plays.to_be_bytes()].concat();
+        aux_chunks.push(Chunk {
+            name: *b"acTL",
+            data: actl,
+        });
+        let default = apng::Frame::from_image(&self.png, &self.frame_control)?;
+        aux_chunks.push(Chunk {
+            name: *b"fcTL",
+            data: default.fctl_data(0),
+        });
+        let frames = self
+            .frames
+            .par_iter()
+            .map(|(image, control)| apng::Frame::from_image(image, control))
+            .collect::<PngResult<Vec<_>>>()?;
+        let mut png = PngData {
+            raw: self.png.clone(),
+            idat_data: default.data,
+            filters: Vec::new(),
+            aux_chunks,
+            frames,
+        };
+        let initial = png.output();
+
+        // The initial encoding is not worth keeping, so the frames are always recompressed
+        let mut opts = opts.to_owned();
+        opts.idat_recoding = true;
+        let deadline = Arc::new(Deadline::new(opts.timeout));
+        optimize_png(&mut png, &initial, &opts, deadline)
+    }
 }
 
 /// Perform optimization on the input file using the options provided
@@ -349,20 +469,58 @@ fn optimize_png(
     debug!("    IDAT size = {idat_original_size} bytes");
     debug!("    File size = {file_original_size} bytes");
 
+    // When errors are fixed, the output can only be validated against the repaired input
+    #[cfg(feature = "sanity-checks")]
+    let repaired_data = opts.fix_errors.then(|| png.output());
+    #[cfg(feature = "sanity-checks")]
+    let original_data = repaired_data.as_deref().unwrap_or(original_data);
+
     let mut opts = opts.to_owned();
+    // Identical frames of an APNG are merged first, which may leave a plain PNG
+    if png.aux_chunks.iter().any(|c| &c.name == b"acTL") && apng::collapse_frames(png, &deadline) {
+        let animated = png.aux_chunks.iter().any(|c| &c.name == b"acTL");
+        debug!("Merged identical APNG frames, animated: {animated}");
+    }
+    apply_metadata_edits(&mut png.aux_chunks, &opts.metadata, opts.deflate)?;
     preprocess_chunks(&mut png.aux_chunks, &mut opts);
+    validate_row_filters(&raw.ihdr, &opts)?;
 
-    let max_size = if opts.force {
+    let mut max_size = if opts.force {
         None
     } else {
         Some(png.raw.estimated_output_size(&png.idat_data))
     };
-    if let Some(result) = optimize_raw(raw.clone(), &opts, deadline.clone(), max_size) {
+    // The frames of an APNG share the same format, so reductions must be applied to all of them
+    if !png.frames.is_empty() && reduce_frames(png, &mut opts, &deadline) {
+        // The main image must now be recompressed regardless of its size
+        max_size = None;
+    }
+    // Frames may be reduced to the regions that change, which also requires recompression
+    if !png.frames.is_empty()
+        && opts.idat_recoding
+        && apng::rediff_frames(png, evaluation_deflater(&opts), &deadline)
+    {
+        max_size = None;
+    }
+    // The original filters are only kept for the main image, so they can't be used for an APNG
+    let original_filters = if png.frames.is_empty() {
+        png.filters.as_slice()
+    } else {
+        &[]
+    };
+    if let Some(result) = optimize_raw(
+        png.raw.clone(),
+        &opts,
+        deadline.clone(),
+        max_size,
+        original_filters,
+    ) {
         png.raw = result.image;
         png.idat_data = result.data;
-        recompress_frames(png, &opts, deadline, result.filter)?;
-        postprocess_chunks(&mut png.aux_chunks, &png.raw.ihdr, &raw.ihdr);
+        recompress_frames(png, &opts, deadline)?;
     }
+    // The format may have changed while reducing the frames, even if no better result was found
+    postprocess_chunks(&mut png.aux_chunks, &png.raw.ihdr, &raw.ihdr, opts.deflate);
 
     let output = png.output();
 
@@ -395,7 +553,9 @@ fn optimize_png(
         );
     }
 
-    if opts.interlace == Some(Interlacing::Adam7) && png.raw.ihdr.interlaced != Interlacing::Adam7 {
+    if opts.interlace == InterlaceMode::Set(Interlacing::Adam7)
+        && png.raw.ihdr.interlaced != Interlacing::Adam7
+    {
         warn!("Interlacing was not enabled as it would result in a larger file. To override this, use `--force`.");
     }
 
@@ -406,45 +566,67 @@ fn optimize_png(
 }
 
 /// Perform optimization on the input image data using the options provided
+/// If the filters of the input are given, they will be evaluated along with the selected filters,
+/// as long as more than one filter is selected
 fn optimize_raw(
     image: Arc<PngImage>,
     opts: &Options,
     deadline: Arc<Deadline>,
     max_size: Option<usize>,
+    original_filters: &[RowFilter],
 ) -> Option<Candidate> {
-    // Libdeflate has four algorithms: 0 = 'uncompressed', 1-4 = 'greedy', 5-7 = 'lazy', 8-9 = 'lazy2', 10-12 = 'near-optimal'
-    // 5 is the minimumm required for a decent evaluation result
-    // 7 is not noticeably slower than 5 and improves evaluation of filters in 'fast' mode (o2 and lower)
-    // 8 is a little slower but not noticeably when used only for reductions (o3 and higher)
-    // 9 is not appreciably better than 8
-    // 10 and higher are quite slow - good for filters but only good for reductions if matching the main zc level
-    let compression = match opts.deflate {
-        Deflaters::Libdeflater { compression } => {
-            if opts.fast_evaluation { 7 } else { 8 }.min(compression)
-        }
-        _ => 8,
+    // In exhaustive mode, every reduction is evaluated with all selected filters and the main deflater
+    let exhaustive = opts.exhaustive && !opts.filter.is_empty();
+    let eval_deflater = if exhaustive {
+        opts.deflate
+    } else {
+        evaluation_deflater(opts)
     };
-    let eval_deflater = Deflaters::Libdeflater { compression };
     // If only one filter is selected, use this for evaluations
-    let eval_filters = if opts.filter.len() == 1 {
+    let eval_filters = if exhaustive || opts.filter.len() == 1 {
         opts.filter.clone()
     } else {
         // None and Bigrams work well together, especially for alpha reductions
         indexset! {RowFilter::None, RowFilter::Bigrams}
     };
+    // Custom filters are otherwise left until the final trials
+    let eval_custom = if exhaustive {
+        custom_filters(opts)
+    } else {
+        Vec::new()
+    };
+    // Alpha optimization is otherwise left until the final trials
+    let eval_alpha =
+        exhaustive && opts.optimize_alpha && opts.alpha_fill.contains(&AlphaFill::Predictor);
     // This will collect all versions of images and pick one that compresses best
     let eval = Evaluator::new(
         deadline.clone(),
         eval_filters.clone(),
         eval_deflater,
-        false,
+        eval_alpha,
         opts.deflate == eval_deflater,
+        ModelSettings::from(opts),
+        eval_custom.clone(),
     );
     let mut new_image = perform_reductions(image.clone(), opts, &deadline, &eval);
-    let eval_result = eval.get_best_candidate();
+    let mut eval_result = eval.get_best_candidate();
     if let Some(ref result) = eval_result {
         new_image = result.image.clone();
     }
+    if let InterlaceMode::Auto { tolerance } = opts.interlace {
+        let new_eval = || {
+            Evaluator::new(
+                deadline.clone(),
+                eval_filters.clone(),
+                eval_deflater,
+                eval_alpha,
+                opts.deflate == eval_deflater,
+                ModelSettings::from(opts),
+                eval_custom.clone(),
+            )
+        };
+        (new_image, eval_result) = choose_interlacing(new_image, eval_result, tolerance, new_eval);
+    }
     let reduction_occurred = new_image.ihdr.color_type != image.ihdr.color_type
         || new_image.ihdr.bit_depth != image.ihdr.bit_depth
         || new_image.ihdr.interlaced != image.ihdr.interlaced;
@@ -454,6 +636,16 @@ fn optimize_raw(
     }
 
     let (result, deflater) = if opts.idat_recoding || reduction_occurred {
+        let mut custom = custom_filters(opts);
+        // The filters of the input can be reused as long as the pixel layout is unchanged
+        // This is an extra trial, so it is skipped when only a single filter is to be tried
+        if !reduction_occurred
+            && opts.filter.len() + custom.len() > 1
+            && opts.row_filters.is_none()
+            && original_filters.len() == new_image.ihdr.num_lines()
+        {
+            custom.push(Filter::Original(original_filters.into()));
+        }
         let result = perform_trials(
             new_image.clone(),
             opts,
@@ -462,6 +654,7 @@ fn optimize_raw(
             eval_result,
             eval_filters,
             eval_deflater,
+            custom,
         );
         (result?, opts.deflate)
     } else {
@@ -480,39 +673,131 @@ fn optimize_raw(
     None
 }
 
+/// Get the deflater to use for evaluations, when not in exhaustive mode
+fn evaluation_deflater(opts: &Options) -> Deflaters {
+    // Libdeflate has four algorithms: 0 = 'uncompressed', 1-4 = 'greedy', 5-7 = 'lazy', 8-9 = 'lazy2', 10-12 = 'near-optimal'
+    // 5 is the minimumm required for a decent evaluation result
+    // 7 is not noticeably slower than 5 and improves evaluation of filters in 'fast' mode (o2 and lower)
+    // 8 is a little slower but not noticeably when used only for reductions (o3 and higher)
+    // 9 is not appreciably better than 8
+    // 10 and higher are quite slow - good for filters but only good for reductions if matching the main zc level
+    let compression = match opts.deflate {
+        Deflaters::Libdeflater { compression } => {
+            if opts.fast_evaluation { 7 } else { 8 }.min(compression)
+        }
+        _ => 8,
+    };
+    Deflaters::Libdeflater { compression }
+}
+
+/// Evaluate the image with the alternative interlacing type and keep the preferred version
+fn choose_interlacing(
+    image: Arc<PngImage>,
+    eval_result: Option<Candidate>,
+    tolerance: f32,
+    new_eval: impl Fn() -> Evaluator,
+) -> (Arc<PngImage>, Option<Candidate>) {
+    let other_interlacing = match image.ihdr.interlaced {
+        Interlacing::None => Interlacing::Adam7,
+        Interlacing::Adam7 => Interlacing::None,
+    };
+    let Some(other) = image.change_interlacing(other_interlacing) else {
+        return (image, eval_result);
+    };
+
+    // The current image only needs to be evaluated if this wasn't already done during reductions
+    let current_eval = eval_result.is_none().then(|| {
+        let eval = new_eval();
+        let description = format!("{} ({})", image.ihdr.color_type, image.ihdr.interlaced);
+        eval.try_image_with_description(image.clone(), &description);
+        eval
+    });
+    let other_eval = new_eval();
+    let description = format!("{} ({})", other.ihdr.color_type, other_interlacing);
+    other_eval.try_image_with_description(Arc::new(other), &description);
+    let current = eval_result.or_else(|| current_eval?.get_best_candidate());
+    let Some(other) = other_eval.get_best_candidate() else {
+        return (image, current);
+    };
+    let Some(current) = current else {
+        return (other.image.clone(), Some(other));
+    };
+
+    let (progressive, interlaced) = if other_interlacing == Interlacing::Adam7 {
+        (current, other)
+    } else {
+        (other, current)
+    };
+    let max_size = progressive.estimated_output_size as f64 * (1.0 + f64::from(tolerance) / 100.0);
+    let preferred = if interlaced.estimated_output_size as f64 <= max_size {
+        interlaced
+    } else {
+        progressive
+    };
+    (preferred.image.clone(), Some(preferred))
+}
+
 /// Perform compression trials
+#[allow(clippy::too_many_arguments)]
 fn perform_trials(
     image: Arc<PngImage>,
     opts: &Options,
     deadline: Arc<Deadline>,
     max_size: Option<usize>,
     mut eval_result: Option<Candidate>,
-    eval_filters: IndexSet<RowFilter>,
+    mut eval_filters: IndexSet<RowFilter>,
     eval_deflater: Deflaters,
+    mut custom: Vec<Filter>,
 ) -> Option<Candidate> {
     let mut filters = opts.filter.clone();
-    let fast_eval = opts.fast_evaluation && (filters.len() > 1 || eval_result.is_some());
+    if opts.row_filters.is_some() {
+        // The given row filters are used instead of any others, so prior evaluations are discarded
+        filters.clear();
+        eval_filters.clear();
+        eval_result = None;
+    }
+    // The predictor is applied during filtering, other alpha fills are evaluated as separate images
+    let alpha_predictor = opts.optimize_alpha && opts.alpha_fill.contains(&AlphaFill::Predictor);
+    let alpha_fills = alpha_fill_variants(&image, opts, &deadline);
+    let fast_eval = opts.fast_evaluation
+        && (filters.len() + custom.len() > 1 || eval_result.is_some() || !alpha_fills.is_empty());
     if fast_eval {
         // Perform a fast evaluation of selected filters followed by a single main compression trial
 
+        // Alpha fills have not been evaluated yet and need to try all filters
+        let fill_filters = if filters.is_empty() {
+            eval_filters.clone()
+        } else {
+            filters.clone()
+        };
         if eval_result.is_some() {
             // Some filters have already been evaluated, we don't need to try them again
             filters = filters.difference(&eval_filters).copied().collect();
+            // Custom filters from the options will also have been evaluated in exhaustive mode
+            if opts.exhaustive && !opts.filter.is_empty() {
+                custom.retain(|f| matches!(f, Filter::Original(_)));
+            }
         }
+        let has_filters = !filters.is_empty() || !custom.is_empty();
 
-        if !filters.is_empty() {
+        if has_filters || !alpha_fills.is_empty() {
             trace!("Evaluating {} filters", filters.len());
             let eval = Evaluator::new(
                 deadline.clone(),
-                filters,
+                filters.clone(),
                 eval_deflater,
-                opts.optimize_alpha,
+                alpha_predictor,
                 opts.deflate == eval_deflater,
+                ModelSettings::from(opts),
+                custom,
             );
             if let Some(result) = &eval_result {
                 eval.set_best_size(result.estimated_output_size);
             }
-            eval.try_image(image.clone());
+            if has_filters {
+                eval.try_image(image.clone());
+            }
+            try_alpha_fills(&eval, alpha_fills, &fill_filters);
             if let Some(result) = eval.get_best_candidate() {
                 eval_result = Some(result);
             }
@@ -542,7 +827,8 @@ fn perform_trials(
 
     // Perform full compression trials of selected filters and determine the best
 
-    if filters.is_empty() {
+    // The original filters are only an addition, so still pick one if there are no others
+    if filters.is_empty() && custom.iter().all(|f| matches!(f, Filter::Original(_))) {
         // Pick a filter automatically
         if image.ihdr.bit_depth as u8 >= 8 {
             // Bigrams is the best all-rounder when there's at least one byte per pixel
@@ -553,13 +839,125 @@ fn perform_trials(
         }
     }
 
-    debug!("Trying {} filters with {}", filters.len(), opts.deflate);
-    let eval = Evaluator::new(deadline, filters, opts.deflate, opts.optimize_alpha, true);
+    debug!(
+        "Trying {} filters with {}",
+        filters.len() + custom.len(),
+        opts.deflate
+    );
+    let eval = Evaluator::new(
+        deadline,
+        filters.clone(),
+        opts.deflate,
+        alpha_predictor,
+        true,
+        ModelSettings::from(opts),
+        custom,
+    );
     if let Some(max_size) = max_size {
         eval.set_best_size(max_size);
     }
-    eval.try_image(image);
-    eval.get_best_candidate()
+    // In exhaustive mode the image has already been evaluated with all filters and the main deflater
+    let exhaustive_result = eval_result.filter(|r| opts.exhaustive && r.data_is_compressed);
+    if let Some(result) = &exhaustive_result {
+        eval.set_best_size(result.estimated_output_size);
+    } else {
+        eval.try_image(image);
+    }
+    try_alpha_fills(&eval, alpha_fills, &filters);
+    eval.get_best_candidate().or(exhaustive_result)
+}
+
+/// Create versions of the image using each of the selected alpha fills, other than the predictor
+fn alpha_fill_variants(
+    image: &Arc<PngImage>,
+    opts: &Options,
+    deadline: &Deadline,
+) -> Vec<(Arc<PngImage>, AlphaFill)> {
+    let mut variants = Vec::new();
+    if !opts.optimize_alpha || !image.ihdr.color_type.has_alpha() {
+        return variants;
+    }
+    for &fill in &opts.alpha_fill {
+        if deadline.passed() {
+            break;
+        }
+        match fill {
+            AlphaFill::Predictor => {}
+            // The image has already been cleaned to black, so only needs to be tried separately
+            // if the main evaluation is using the predictor
+            AlphaFill::Black => {
+                if opts.alpha_fill.contains(&AlphaFill::Predictor) {
+                    variants.push((image.clone(), fill));
+                }
+            }
+            _ => {
+                if let Some(filled) = filled_alpha_channel(image, fill) {
+                    variants.push((Arc::new(filled), fill));
+                }
+            }
+        }
+    }
+    variants
+}
+
+/// Enter the alpha fill variants into the evaluator
+fn try_alpha_fills(
+    eval: &Evaluator,
+    variants: Vec<(Arc<PngImage>, AlphaFill)>,
+    filters: &IndexSet<RowFilter>,
+) {
+    for (image, fill) in variants {
+        let description = format!("{} ({} fill)", image.ihdr.color_type, fill);
+        eval.try_image_with_filters(image, &description, filters.clone(), false);
+    }
+}
+
+/// Collect the custom filters from the options, to be evaluated in addition to the selected filters
+fn custom_filters(opts: &Options) -> Vec<Filter> {
+    if let Some(row_filters) = &opts.row_filters {
+        return vec![Filter::Rows(row_filters.as_slice().into())];
+    }
+    opts.filter_strategies
+        .iter()
+        .cloned()
+        .enumerate()
+        .map(|(i, strategy)| Filter::Custom(i, strategy))
+        .collect()
+}
+
+/// Check that any row filters in the options are valid for the image
+fn validate_row_filters(ihdr: &IhdrData, opts: &Options) -> PngResult<()> {
+    let Some(row_filters) = &opts.row_filters else {
+        return Ok(());
+    };
+    if matches!(opts.interlace, InterlaceMode::Auto { .. }) {
+        return Err(PngError::new(
+            "Row filters cannot be used with automatic interlacing",
+        ));
+    }
+    if let Some(filter) = row_filters.iter().find(|&&f| f > RowFilter::Paeth) {
+        return Err(PngError::new(&format!(
+            "Row filters must be standard filters, found {filter}"
+        )));
+    }
+    // The rows are those of the output image, which may have different interlacing
+    let ihdr = IhdrData {
+        interlaced: match opts.interlace {
+            InterlaceMode::Set(interlacing) => interlacing,
+            _ => ihdr.interlaced,
+        },
+        ..ihdr.clone()
+    };
+    let expected = ihdr.num_lines();
+    if row_filters.len() != expected {
+        return Err(PngError::new(&format!(
+            "Number of row filters {} does not match the {} rows of the {} image",
+            row_filters.len(),
+            expected,
+            ihdr.interlaced
+        )));
+    }
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -620,16 +1018,109 @@ fn report_format(prefix: &str, png: &PngImage) {
     );
 }
 
+/// Apply reductions to the main image and all frames of an APNG, returning true if successful
+///
+/// The frames must all share the same format, so reductions are evaluated on a combination of
+/// every frame and the chosen format is then applied to each of them. Reductions are disabled in
+/// the options afterwards, as the main image can no longer be reduced by itself.
+fn reduce_frames(png: &mut PngData, opts: &mut Options, deadline: &Arc<Deadline>) -> bool {
+    let reduction_opts = opts.clone();
+    opts.bit_depth_reduction = false;
+    opts.color_type_reduction = false;
+    opts.palette_reduction = false;
+    if !(reduction_opts.bit_depth_reduction
+        || reduction_opts.color_type_reduction
+        || reduction_opts.palette_reduction)
+        || deadline.passed()
+    {
+        return false;
+    }
+
+    let frames: PngResult<Vec<_>> = png
+        .frames
+        .par_iter()
+        .map(|frame| frame.image(&png.raw.ihdr))
+        .collect();
+    let frames = match frames {
+        Ok(frames) => frames,
+        Err(e) => {
+            warn!("Unable to decode APNG frames, skipping reductions: {e}");
+            return false;
+        }
+    };
+    let images: Vec<_> = std::iter::once(&*png.raw).chain(&frames).collect();
+    let Some(combined) = apng::stacked_image(&images) else {
+        return false;
+    };
+
+    let eval_deflater = evaluation_deflater(opts);
+    let eval = Evaluator::new(
+        deadline.clone(),
+        indexset! {RowFilter::None, RowFilter::Bigrams},
+        eval_deflater,
+        false,
+        false,
+        ModelSettings::from(&*opts),
+        Vec::new(),
+    );
+    let baseline = perform_reductions(Arc::new(combined), &reduction_opts, deadline, &eval);
+    let reduced = eval
+        .get_best_candidate()
+        .map_or(baseline, |result| result.image);
+    let ihdr = &reduced.ihdr;
+    if ihdr.color_type == png.raw.ihdr.color_type && ihdr.bit_depth == png.raw.ihdr.bit_depth {
+        return false;
+    }
+
+    // Convert every frame to the new format and compress it quickly, to be recompressed later
+    let convert = |image: &PngImage| {
+        let converted = convert::converted_to_format(
+            image,
+            &ihdr.color_type,
+            ihdr.bit_depth,
+            reduction_opts.optimize_alpha,
+            reduction_opts.scale_16,
+        )?;
+        let data = eval_deflater
+            .deflate(&converted.filter_image(RowFilter::None, false), None)
+            .ok()?;
+        Some((converted, data))
+    };
+    let Some((image, idat_data)) = convert(&png.raw) else {
+        warn!(
+            "Unable to convert APNG to {}, skipping reductions",
+            ihdr.color_type
+        );
+        return false;
+    };
+    let Some(frame_data) = frames.par_iter().map(convert).collect::<Option<Vec<_>>>() else {
+        warn!(
+            "Unable to convert APNG to {}, skipping reductions",
+            ihdr.color_type
+        );
+        return false;
+    };
+
+    report_format("Transformed animation to ", &image);
+    png.raw = Arc::new(image);
+    png.idat_data = idat_data;
+    for (frame, (_, data)) in png.frames.iter_mut().zip(frame_data) {
+        frame.data = data;
+    }
+    // If any type of reduction is performed, the frames must be recompressed
+    opts.idat_recoding = true;
+    true
+}
+
 /// Recompress the additional frames of an APNG
-fn recompress_frames(
-    png: &mut PngData,
-    opts: &Options,
-    deadline: Arc<Deadline>,
-    filter: RowFilter,
-) -> PngResult<()> {
-    if !opts.idat_recoding || png.frames.is_empty() {
+fn recompress_frames(png: &mut PngData, opts: &Options, deadline: Arc<Deadline>) -> PngResult<()> {
+    // Row filters only apply to the main image, so leave the frames as they are
+    if !opts.idat_recoding || png.frames.is_empty() || opts.row_filters.is_some() {
         return Ok(());
     }
+    // Each frame is evaluated independently, as its content may be very different to the main image
+    let eval_deflater = evaluation_deflater(opts);
+    let custom = custom_filters(opts);
     png.frames
         .par_iter_mut()
         .with_max_len(1)
@@ -641,17 +1132,29 @@ fn recompress_frames(
             let mut ihdr = png.raw.ihdr.clone();
             ihdr.width = frame.width;
             ihdr.height = frame.height;
-            let image = PngImage::new(ihdr, &frame.data)?;
-            let filtered = image.filter_image(filter, opts.optimize_alpha);
-            let max_size = Some(frame.data.len() - 1);
-            if let Ok(data) = opts.deflate.deflate(&filtered, max_size) {
+            let image = Arc::new(PngImage::new(ihdr, &frame.data)?);
+            let max_size = image.estimated_output_size(&frame.data);
+            let Some(result) = perform_trials(
+                image,
+                opts,
+                deadline.clone(),
+                Some(max_size),
+                None,
+                opts.filter.clone(),
+                eval_deflater,
+                custom.clone(),
+            ) else {
+                return Ok(());
+            };
+            if result.data_is_compressed && result.estimated_output_size < max_size {
                 debug!(
-                    "Recompressed fdAT #{:<2}: {} ({} bytes decrease)",
+                    "Recompressed fdAT #{:<2}: {} ({} bytes decrease), f = {}",
                     i,
-                    data.len(),
-                    frame.data.len() - data.len()
+                    result.data.len(),
+                    frame.data.len() - result.data.len(),
+                    result.filter
                 );
-                frame.data = data;
+                frame.data = result.data;
             }
             Ok(())
         })
@@ -659,7 +1162,10 @@ fn recompress_frames(
 
 /// Check if an image was already optimized prior to oxipng's operations
 fn is_fully_optimized(original_size: usize, optimized_size: usize, opts: &Options) -> bool {
-    original_size <= optimized_size && !opts.force
+    original_size <= optimized_size
+        && !opts.force
+        && opts.metadata.is_empty()
+        && opts.strip_exif.is_empty()
 }
 
 fn copy_permissions(metadata_input: &Metadata, out_file: &File) -> PngResult<()> {
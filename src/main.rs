@@ -26,7 +26,10 @@ use clap::ArgMatches;
 mod cli;
 use indexmap::IndexSet;
 use log::{error, warn, Level, LevelFilter};
-use oxipng::{Deflaters, InFile, Options, OutFile, PngError, RowFilter, StripChunks};
+use oxipng::{
+    is_valid_keyword, AlphaFill, ChunkMetadata, Deflaters, ExifGroup, InFile, InterlaceMode,
+    MetadataEdit, Options, OutFile, PhysUnit, PngError, RowFilter, StripChunks, StripText,
+};
 use rayon::prelude::*;
 
 use crate::cli::DISPLAY_CHUNKS;
@@ -195,6 +198,10 @@ fn parse_opts_into_struct(
     let mut opts = match matches.get_one::<String>("optimization") {
         None => Options::default(),
         Some(x) if x == "max" => Options::max_compression(),
+        Some(x) if x == "max+" => Options {
+            exhaustive: true,
+            ..Options::max_compression()
+        },
         Some(level) => Options::from_preset(level.parse::<u8>().unwrap()),
     };
 
@@ -205,6 +212,14 @@ fn parse_opts_into_struct(
         }
     }
 
+    if let Some(&window) = matches.get_one::<i64>("brute-window") {
+        opts.brute_window = window as usize;
+    }
+
+    if let Some(&level) = matches.get_one::<i64>("brute-level") {
+        opts.brute_level = level as u8;
+    }
+
     if let Some(&num) = matches.get_one::<u64>("timeout") {
         opts.timeout = Some(Duration::from_secs(num));
     }
@@ -239,6 +254,13 @@ fn parse_opts_into_struct(
 
     opts.optimize_alpha = matches.get_flag("alpha");
 
+    if let Some(fills) = matches.get_one::<String>("alpha-fill") {
+        opts.alpha_fill = fills
+            .split(',')
+            .map(parse_alpha_fill)
+            .collect::<Result<_, _>>()?;
+    }
+
     opts.scale_16 = matches.get_flag("scale16");
 
     // The default value for fast depends on the preset - make sure we don't change when not provided
@@ -246,6 +268,10 @@ fn parse_opts_into_struct(
         opts.fast_evaluation = matches.get_flag("fast");
     }
 
+    if matches.get_flag("exhaustive") {
+        opts.exhaustive = true;
+    }
+
     opts.force = matches.get_flag("force");
 
     opts.fix_errors = matches.get_flag("fix");
@@ -263,19 +289,29 @@ fn parse_opts_into_struct(
         opts.color_type_reduction = false;
         opts.palette_reduction = false;
         opts.grayscale_reduction = false;
-        opts.interlace = None;
+        opts.interlace = InterlaceMode::Keep;
     }
 
     opts.idat_recoding = !matches.get_flag("no-recoding");
 
     if let Some(x) = matches.get_one::<String>("interlace") {
-        opts.interlace = if x == "keep" {
-            None
-        } else {
-            x.parse::<u8>().unwrap().try_into().ok()
+        opts.interlace = match x.as_str() {
+            "auto" => InterlaceMode::Auto { tolerance: 0.0 },
+            "keep" => InterlaceMode::Keep,
+            _ => InterlaceMode::Set(x.parse::<u8>().unwrap().try_into().unwrap()),
         };
     }
 
+    if let Some(&tolerance) = matches.get_one::<f32>("interlace-tolerance") {
+        if !matches!(opts.interlace, InterlaceMode::Auto { .. }) {
+            return Err("--interlace-tolerance requires `-i auto`".to_owned());
+        }
+        if tolerance.is_nan() || tolerance < 0.0 {
+            return Err(format!("Invalid interlace tolerance: {tolerance}"));
+        }
+        opts.interlace = InterlaceMode::Auto { tolerance };
+    }
+
     if let Some(keep) = matches.get_one::<String>("keep") {
         let mut keep_display = false;
         let mut names = keep
@@ -326,6 +362,59 @@ fn parse_opts_into_struct(
         opts.strip = StripChunks::Safe;
     }
 
+    if let Some(keywords) = matches.get_one::<String>("strip-text") {
+        opts.strip_text =
+            StripText::Strip(keywords.split(',').map(|k| k.trim().to_owned()).collect());
+    }
+
+    if let Some(keywords) = matches.get_one::<String>("keep-text") {
+        opts.strip_text =
+            StripText::Keep(keywords.split(',').map(|k| k.trim().to_owned()).collect());
+    }
+
+    if let Some(groups) = matches.get_one::<String>("strip-exif") {
+        opts.strip_exif = groups
+            .split(',')
+            .map(parse_exif_group)
+            .collect::<Result<_, _>>()?;
+    }
+
+    if let Some(properties) = matches.get_one::<String>("strip-xmp") {
+        opts.strip_xmp = properties
+            .split(',')
+            .map(parse_xmp_property)
+            .collect::<Result<_, _>>()?;
+    }
+
+    if let Some(texts) = matches.get_many::<String>("set-text") {
+        for text in texts {
+            opts.metadata.push(parse_text(text, false)?);
+        }
+    }
+
+    if let Some(texts) = matches.get_many::<String>("set-itxt") {
+        for text in texts {
+            opts.metadata.push(parse_text(text, true)?);
+        }
+    }
+
+    if let Some(size) = matches.get_one::<String>("set-phys") {
+        opts.metadata.push(MetadataEdit::Replace(parse_phys(size)?));
+    }
+
+    if let Some(intent) = matches.get_one::<String>("set-srgb") {
+        let intent = match intent.as_str() {
+            "perceptual" => 0,
+            "relative" => 1,
+            "saturation" => 2,
+            _ => 3,
+        };
+        // An image should not have both an ICC profile and an sRGB chunk
+        opts.metadata.push(MetadataEdit::Remove(*b"iCCP"));
+        opts.metadata
+            .push(MetadataEdit::Replace(ChunkMetadata::Srgb(intent)));
+    }
+
     #[cfg(feature = "zopfli")]
     if matches.get_flag("zopfli") {
         let iterations = *matches.get_one::<i64>("iterations").unwrap();
@@ -357,6 +446,89 @@ fn parse_chunk_name(name: &str) -> Result<[u8; 4], String> {
         .map_err(|_| format!("Invalid chunk name {name}"))
 }
 
+fn parse_alpha_fill(name: &str) -> Result<AlphaFill, String> {
+    match name.trim() {
+        "black" => Ok(AlphaFill::Black),
+        "left" => Ok(AlphaFill::Left),
+        "up" => Ok(AlphaFill::Up),
+        "predictor" => Ok(AlphaFill::Predictor),
+        "nearest" => Ok(AlphaFill::Nearest),
+        _ => Err(format!("Invalid alpha fill {name}")),
+    }
+}
+
+fn parse_exif_group(name: &str) -> Result<ExifGroup, String> {
+    match name.trim() {
+        "gps" => Ok(ExifGroup::Gps),
+        "serial" => Ok(ExifGroup::Serial),
+        "thumbnail" => Ok(ExifGroup::Thumbnail),
+        _ => Err(format!("Invalid Exif group {name}")),
+    }
+}
+
+fn parse_xmp_property(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    match name.split_once(':') {
+        Some((prefix, local)) if !prefix.is_empty() && !local.is_empty() => Ok(name.to_owned()),
+        _ => Err(format!("Invalid XMP property {name}")),
+    }
+}
+
+fn parse_text(arg: &str, international: bool) -> Result<MetadataEdit, String> {
+    let (keyword, text) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid text {arg}, expected <keyword>=<text>"))?;
+    if !is_valid_keyword(keyword) {
+        return Err(format!("Invalid keyword {keyword:?}"));
+    }
+    let (keyword, text) = (keyword.to_owned(), text.to_owned());
+    let latin1 = text.chars().all(|c| u8::try_from(c).is_ok());
+    Ok(MetadataEdit::Replace(if international || !latin1 {
+        ChunkMetadata::InternationalText {
+            keyword,
+            language: String::new(),
+            translated_keyword: String::new(),
+            text,
+            compressed: false,
+        }
+    } else {
+        ChunkMetadata::Text { keyword, text }
+    }))
+}
+
+fn parse_phys(size: &str) -> Result<ChunkMetadata, String> {
+    let error = || format!("Invalid physical size {size}");
+    let size = size.trim();
+    // The value is either a resolution in pixels per meter, or an aspect ratio with no unit
+    let (value, scale, separator) = if let Some(value) = size.strip_suffix("dpi") {
+        (value, 1.0 / 0.0254, 'x')
+    } else if let Some(value) = size.strip_suffix("ppm") {
+        (value, 1.0, 'x')
+    } else if size.contains(':') {
+        (size, 1.0, ':')
+    } else {
+        return Err(error());
+    };
+    let (x, y) = value.split_once(separator).unwrap_or((value, value));
+    let parse = |v: &str| {
+        let v = v.trim().parse::<f64>().map_err(|_| error())? * scale;
+        if (0.5..=f64::from(u32::MAX)).contains(&v) {
+            Ok(v.round() as u32)
+        } else {
+            Err(error())
+        }
+    };
+    Ok(ChunkMetadata::PhysicalDimensions {
+        x: parse(x)?,
+        y: parse(y)?,
+        unit: if separator == ':' {
+            PhysUnit::Unknown
+        } else {
+            PhysUnit::Meter
+        },
+    })
+}
+
 fn parse_numeric_range_opts(
     input: &str,
     min_value: u8,
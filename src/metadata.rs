@@ -0,0 +1,523 @@
+use log::{debug, warn};
+
+use crate::{
+    deflate::{inflate, Deflaters},
+    error::PngError,
+    headers::{file_header_is_valid, parse_next_chunk, read_be_u16, read_be_u32, Chunk},
+    PngResult,
+};
+
+/// The chunks that can contain text, which may be matched by keyword
+const TEXT_CHUNKS: [[u8; 4]; 3] = [*b"tEXt", *b"zTXt", *b"iTXt"];
+
+/// Unit of the pixel dimensions in a pHYs chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhysUnit {
+    /// The dimensions only define the aspect ratio of the pixels
+    Unknown = 0,
+    /// The dimensions are in pixels per meter
+    Meter = 1,
+}
+
+/// An ancillary chunk of a PNG file, decoded into its fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ChunkMetadata {
+    /// A tEXt chunk, holding uncompressed Latin-1 text
+    Text { keyword: String, text: String },
+    /// A zTXt chunk, holding compressed Latin-1 text
+    CompressedText { keyword: String, text: String },
+    /// An iTXt chunk, holding UTF-8 text that may be compressed
+    InternationalText {
+        keyword: String,
+        /// Language of the text, such as "en-US", or empty if unknown
+        language: String,
+        /// The keyword translated into the language of the text
+        translated_keyword: String,
+        text: String,
+        compressed: bool,
+    },
+    /// An eXIf chunk, holding Exif data
+    Exif(Vec<u8>),
+    /// A pHYs chunk, holding the physical pixel dimensions
+    PhysicalDimensions { x: u32, y: u32, unit: PhysUnit },
+    /// A tIME chunk, holding the time of the last modification in UTC
+    Time {
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    },
+    /// A gAMA chunk, holding the image gamma multiplied by 100000
+    Gamma(u32),
+    /// A cHRM chunk, holding the chromaticities multiplied by 100000
+    Chromaticities {
+        white: (u32, u32),
+        red: (u32, u32),
+        green: (u32, u32),
+        blue: (u32, u32),
+    },
+    /// An sRGB chunk, holding the rendering intent
+    Srgb(u8),
+    /// An iCCP chunk, holding the name and decompressed data of an ICC profile
+    IccProfile { name: String, profile: Vec<u8> },
+    /// A cICP chunk, holding the coding-independent code points from ITU-T H.273
+    CodingIndependentCodePoints {
+        color_primaries: u8,
+        transfer_function: u8,
+        matrix_coefficients: u8,
+        full_range: bool,
+    },
+    /// An mDCV chunk, holding the mastering display color volume
+    MasteringDisplayColorVolume {
+        /// The red, green and blue chromaticities multiplied by 50000
+        primaries: [(u16, u16); 3],
+        /// The white point chromaticity multiplied by 50000
+        white_point: (u16, u16),
+        /// The maximum luminance in cd/m² multiplied by 10000
+        max_luminance: u32,
+        /// The minimum luminance in cd/m² multiplied by 10000
+        min_luminance: u32,
+    },
+    /// A cLLI chunk, holding the content light levels in cd/m² multiplied by 10000
+    ContentLightLevel {
+        max_content: u32,
+        max_frame_average: u32,
+    },
+}
+
+/// A change to the metadata of a PNG file, for use in [`Options`][crate::Options]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataEdit {
+    /// Add a chunk, keeping any existing chunks of the same type
+    Insert(ChunkMetadata),
+    /// Add a chunk in place of any existing chunks of the same type, or for text chunks, any
+    /// existing text chunks with the same keyword
+    Replace(ChunkMetadata),
+    /// Remove all chunks of a type, such as `*b"tEXt"`
+    Remove([u8; 4]),
+    /// Remove all text chunks with a keyword
+    RemoveText(String),
+}
+
+impl ChunkMetadata {
+    /// The name of the chunk that holds this metadata
+    #[must_use]
+    pub fn chunk_name(&self) -> [u8; 4] {
+        match self {
+            Self::Text { .. } => *b"tEXt",
+            Self::CompressedText { .. } => *b"zTXt",
+            Self::InternationalText { .. } => *b"iTXt",
+            Self::Exif(_) => *b"eXIf",
+            Self::PhysicalDimensions { .. } => *b"pHYs",
+            Self::Time { .. } => *b"tIME",
+            Self::Gamma(_) => *b"gAMA",
+            Self::Chromaticities { .. } => *b"cHRM",
+            Self::Srgb(_) => *b"sRGB",
+            Self::IccProfile { .. } => *b"iCCP",
+            Self::CodingIndependentCodePoints { .. } => *b"cICP",
+            Self::MasteringDisplayColorVolume { .. } => *b"mDCV",
+            Self::ContentLightLevel { .. } => *b"cLLI",
+        }
+    }
+
+    /// The keyword of a text chunk
+    #[must_use]
+    pub fn keyword(&self) -> Option<&str> {
+        match self {
+            Self::Text { keyword, .. }
+            | Self::CompressedText { keyword, .. }
+            | Self::InternationalText { keyword, .. } => Some(keyword),
+            _ => None,
+        }
+    }
+
+    /// Decode a chunk, returning `None` if it is not supported or is invalid
+    pub(crate) fn from_chunk(name: &[u8; 4], data: &[u8]) -> Option<Self> {
+        let u32_at = |i: usize| data.get(i..i + 4).map(read_be_u32);
+        let point_at = |i: usize| Some((u32_at(i)?, u32_at(i + 4)?));
+        let u16_point_at = |i: usize| {
+            let u16_at = |i: usize| data.get(i..i + 2).map(read_be_u16);
+            Some((u16_at(i)?, u16_at(i + 2)?))
+        };
+        Some(match name {
+            b"tEXt" => {
+                let (keyword, text) = split_null(data)?;
+                Self::Text {
+                    keyword: latin1_to_string(keyword),
+                    text: latin1_to_string(text),
+                }
+            }
+            b"zTXt" => {
+                let (keyword, rest) = split_null(data)?;
+                let (&0, compressed) = rest.split_first()? else {
+                    return None;
+                };
+                Self::CompressedText {
+                    keyword: latin1_to_string(keyword),
+                    text: latin1_to_string(&inflate_unknown_size(compressed).ok()?),
+                }
+            }
+            b"iTXt" => {
+                let (keyword, rest) = split_null(data)?;
+                let (&[flag, method], rest) = (rest.get(..2)?, &rest[2..]) else {
+                    return None;
+                };
+                let (language, rest) = split_null(rest)?;
+                let (translated_keyword, text) = split_null(rest)?;
+                let compressed = match (flag, method) {
+                    (0, _) => false,
+                    (1, 0) => true,
+                    _ => return None,
+                };
+                let text = if compressed {
+                    inflate_unknown_size(text).ok()?
+                } else {
+                    text.to_vec()
+                };
+                Self::InternationalText {
+                    keyword: latin1_to_string(keyword),
+                    language: String::from_utf8(language.to_vec()).ok()?,
+                    translated_keyword: String::from_utf8(translated_keyword.to_vec()).ok()?,
+                    text: String::from_utf8(text).ok()?,
+                    compressed,
+                }
+            }
+            b"eXIf" => Self::Exif(data.to_vec()),
+            b"pHYs" if data.len() == 9 => Self::PhysicalDimensions {
+                x: u32_at(0)?,
+                y: u32_at(4)?,
+                unit: match data[8] {
+                    0 => PhysUnit::Unknown,
+                    1 => PhysUnit::Meter,
+                    _ => return None,
+                },
+            },
+            b"tIME" if data.len() == 7 => Self::Time {
+                year: read_be_u16(&data[0..2]),
+                month: data[2],
+                day: data[3],
+                hour: data[4],
+                minute: data[5],
+                second: data[6],
+            },
+            b"gAMA" if data.len() == 4 => Self::Gamma(u32_at(0)?),
+            b"cHRM" if data.len() == 32 => Self::Chromaticities {
+                white: point_at(0)?,
+                red: point_at(8)?,
+                green: point_at(16)?,
+                blue: point_at(24)?,
+            },
+            b"sRGB" if data.len() == 1 => Self::Srgb(data[0]),
+            b"iCCP" => {
+                let (name, rest) = split_null(data)?;
+                let (&0, compressed) = rest.split_first()? else {
+                    return None;
+                };
+                Self::IccProfile {
+                    name: latin1_to_string(name),
+                    profile: inflate_unknown_size(compressed).ok()?,
+                }
+            }
+            b"cICP" if data.len() == 4 => Self::CodingIndependentCodePoints {
+                color_primaries: data[0],
+                transfer_function: data[1],
+                matrix_coefficients: data[2],
+                full_range: match data[3] {
+                    0 => false,
+                    1 => true,
+                    _ => return None,
+                },
+            },
+            b"mDCV" if data.len() == 24 => Self::MasteringDisplayColorVolume {
+                primaries: [u16_point_at(0)?, u16_point_at(4)?, u16_point_at(8)?],
+                white_point: u16_point_at(12)?,
+                max_luminance: u32_at(16)?,
+                min_luminance: u32_at(20)?,
+            },
+            b"cLLI" if data.len() == 8 => Self::ContentLightLevel {
+                max_content: u32_at(0)?,
+                max_frame_average: u32_at(4)?,
+            },
+            _ => return None,
+        })
+    }
+
+    /// Encode the metadata as a chunk, compressing with the given deflater where required
+    pub(crate) fn to_chunk(&self, deflater: Deflaters) -> PngResult<Chunk> {
+        let mut data = Vec::new();
+        match self {
+            Self::Text { keyword, text } => {
+                data.extend(keyword_bytes(keyword)?);
+                data.push(0);
+                data.extend(string_to_latin1(text)?);
+            }
+            Self::CompressedText { keyword, text } => {
+                data.extend(keyword_bytes(keyword)?);
+                data.extend([0, 0]);
+                data.extend(deflater.deflate(&string_to_latin1(text)?, None)?);
+            }
+            Self::InternationalText {
+                keyword,
+                language,
+                translated_keyword,
+                text,
+                compressed,
+            } => {
+                if language.contains('\0') || translated_keyword.contains('\0') {
+                    return Err(PngError::new("iTXt fields cannot contain null characters"));
+                }
+                data.extend(keyword_bytes(keyword)?);
+                data.extend([0, u8::from(*compressed), 0]);
+                data.extend(language.as_bytes());
+                data.push(0);
+                data.extend(translated_keyword.as_bytes());
+                data.push(0);
+                if *compressed {
+                    data.extend(deflater.deflate(text.as_bytes(), None)?);
+                } else {
+                    data.extend(text.as_bytes());
+                }
+            }
+            Self::Exif(exif) => data.extend(exif),
+            Self::PhysicalDimensions { x, y, unit } => {
+                data.extend(x.to_be_bytes());
+                data.extend(y.to_be_bytes());
+                data.push(*unit as u8);
+            }
+            Self::Time {
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second,
+            } => {
+                data.extend(year.to_be_bytes());
+                data.extend([*month, *day, *hour, *minute, *second]);
+            }
+            Self::Gamma(gamma) => data.extend(gamma.to_be_bytes()),
+            Self::Chromaticities {
+                white,
+                red,
+                green,
+                blue,
+            } => {
+                for (x, y) in [white, red, green, blue] {
+                    data.extend(x.to_be_bytes());
+                    data.extend(y.to_be_bytes());
+                }
+            }
+            Self::Srgb(intent) => data.push(*intent),
+            Self::IccProfile { name, profile } => {
+                data.extend(keyword_bytes(name)?);
+                data.extend([0, 0]);
+                data.extend(deflater.deflate(profile, None)?);
+            }
+            Self::CodingIndependentCodePoints {
+                color_primaries,
+                transfer_function,
+                matrix_coefficients,
+                full_range,
+            } => {
+                data.extend([
+                    *color_primaries,
+                    *transfer_function,
+                    *matrix_coefficients,
+                    u8::from(*full_range),
+                ]);
+            }
+            Self::MasteringDisplayColorVolume {
+                primaries,
+                white_point,
+                max_luminance,
+                min_luminance,
+            } => {
+                for (x, y) in primaries.iter().chain([white_point]) {
+                    data.extend(x.to_be_bytes());
+                    data.extend(y.to_be_bytes());
+                }
+                data.extend(max_luminance.to_be_bytes());
+                data.extend(min_luminance.to_be_bytes());
+            }
+            Self::ContentLightLevel {
+                max_content,
+                max_frame_average,
+            } => {
+                data.extend(max_content.to_be_bytes());
+                data.extend(max_frame_average.to_be_bytes());
+            }
+        }
+        Ok(Chunk {
+            name: self.chunk_name(),
+            data,
+        })
+    }
+}
+
+/// Read the metadata of a PNG file, in the order the chunks appear
+///
+/// Chunks that are not supported by [`ChunkMetadata`] are skipped, as are any that are invalid.
+pub fn read_metadata(png_data: &[u8]) -> PngResult<Vec<ChunkMetadata>> {
+    if !png_data.get(0..8).is_some_and(file_header_is_valid) {
+        return Err(PngError::NotPNG);
+    }
+    let mut byte_offset = 8;
+    let mut metadata = Vec::new();
+    while let Some(chunk) = parse_next_chunk(png_data, &mut byte_offset, false)? {
+        match ChunkMetadata::from_chunk(&chunk.name, chunk.data) {
+            Some(m) => metadata.push(m),
+            None if is_supported(&chunk.name) => warn!(
+                "Skipping invalid {} chunk",
+                String::from_utf8_lossy(&chunk.name)
+            ),
+            None => {}
+        }
+    }
+    Ok(metadata)
+}
+
+/// Apply metadata edits to the aux chunks, in order
+pub fn apply_metadata_edits(
+    aux_chunks: &mut Vec<Chunk>,
+    edits: &[MetadataEdit],
+    deflater: Deflaters,
+) -> PngResult<()> {
+    for edit in edits {
+        match edit {
+            MetadataEdit::Insert(metadata) => {
+                let chunk = metadata.to_chunk(deflater)?;
+                debug!("Inserting {} chunk", display_name(&chunk.name));
+                let idx = metadata_position(aux_chunks);
+                aux_chunks.insert(idx, chunk);
+            }
+            MetadataEdit::Replace(metadata) => {
+                let chunk = metadata.to_chunk(deflater)?;
+                let replaces = |c: &Chunk| match metadata.keyword() {
+                    Some(keyword) => has_keyword(c, keyword),
+                    None => c.name == chunk.name,
+                };
+                debug!("Replacing {} chunk", display_name(&chunk.name));
+                // The first replaced chunk is preceded only by chunks that are kept
+                let idx = aux_chunks
+                    .iter()
+                    .position(replaces)
+                    .unwrap_or_else(|| metadata_position(aux_chunks));
+                aux_chunks.retain(|c| !replaces(c));
+                aux_chunks.insert(idx, chunk);
+            }
+            MetadataEdit::Remove(name) => {
+                if name[0].is_ascii_uppercase() {
+                    return Err(PngError::new(&format!(
+                        "Critical chunk {} cannot be removed",
+                        display_name(name)
+                    )));
+                }
+                debug!("Removing {} chunks", display_name(name));
+                aux_chunks.retain(|c| &c.name != name);
+            }
+            MetadataEdit::RemoveText(keyword) => {
+                debug!("Removing text chunks with keyword {keyword}");
+                aux_chunks.retain(|c| !has_keyword(c, keyword));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether a chunk type is supported by [`ChunkMetadata`]
+fn is_supported(name: &[u8; 4]) -> bool {
+    TEXT_CHUNKS.contains(name)
+        || matches!(
+            name,
+            b"eXIf"
+                | b"pHYs"
+                | b"tIME"
+                | b"gAMA"
+                | b"cHRM"
+                | b"sRGB"
+                | b"iCCP"
+                | b"cICP"
+                | b"mDCV"
+                | b"cLLI"
+        )
+}
+
+/// Whether a chunk is a text chunk with the given keyword
+fn has_keyword(chunk: &Chunk, keyword: &str) -> bool {
+    text_keyword(&chunk.name, &chunk.data).is_some_and(|k| k == keyword)
+}
+
+/// Get the keyword of a text chunk
+pub fn text_keyword(name: &[u8; 4], data: &[u8]) -> Option<String> {
+    if !TEXT_CHUNKS.contains(name) {
+        return None;
+    }
+    split_null(data).map(|(keyword, _)| latin1_to_string(keyword))
+}
+
+/// Position for new metadata chunks, which is before the image data so it is valid for all types
+fn metadata_position(aux_chunks: &[Chunk]) -> usize {
+    aux_chunks
+        .iter()
+        .position(|c| &c.name == b"IDAT")
+        .unwrap_or(aux_chunks.len())
+}
+
+fn display_name(name: &[u8; 4]) -> String {
+    String::from_utf8_lossy(name).into_owned()
+}
+
+/// Split data at the first null separator
+fn split_null(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let idx = data.iter().position(|&b| b == 0)?;
+    Some((&data[..idx], &data[idx + 1..]))
+}
+
+fn latin1_to_string(data: &[u8]) -> String {
+    data.iter().map(|&b| char::from(b)).collect()
+}
+
+fn string_to_latin1(text: &str) -> PngResult<Vec<u8>> {
+    text.chars()
+        .map(|c| u8::try_from(c).map_err(|_| PngError::new("Text must be Latin-1")))
+        .collect()
+}
+
+/// Encode a keyword, if it is valid
+fn keyword_bytes(keyword: &str) -> PngResult<Vec<u8>> {
+    if !is_valid_keyword(keyword) {
+        return Err(PngError::new(&format!("Invalid keyword: {keyword:?}")));
+    }
+    string_to_latin1(keyword)
+}
+
+/// Whether a keyword can be used for a text chunk
+///
+/// It must be 1-79 Latin-1 characters without nulls, or leading, trailing or consecutive spaces.
+#[must_use]
+pub fn is_valid_keyword(keyword: &str) -> bool {
+    let Ok(bytes) = string_to_latin1(keyword) else {
+        return false;
+    };
+    !(bytes.is_empty()
+        || bytes.len() > 79
+        || bytes.contains(&0)
+        || bytes.starts_with(b" ")
+        || bytes.ends_with(b" ")
+        || bytes.windows(2).any(|w| w == b"  "))
+}
+
+/// Decompress data of an unknown size, growing the buffer as needed
+fn inflate_unknown_size(data: &[u8]) -> PngResult<Vec<u8>> {
+    const MAX_SIZE: usize = 1 << 28;
+    let mut size = data.len() * 4 + 1000;
+    loop {
+        match inflate(data, size) {
+            Err(PngError::Other(_)) if size < MAX_SIZE => size *= 4,
+            result => return result,
+        }
+    }
+}
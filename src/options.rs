@@ -1,13 +1,23 @@
 use std::{
     fmt,
     path::{Path, PathBuf},
+    sync::Arc,
     time::Duration,
 };
 
 use indexmap::{indexset, IndexSet};
 use log::warn;
 
-use crate::{deflate::Deflaters, filters::RowFilter, headers::StripChunks, interlace::Interlacing};
+use crate::{
+    deflate::Deflaters,
+    exif::ExifGroup,
+    filters::RowFilter,
+    headers::{StripChunks, StripText},
+    interlace::{InterlaceMode, Interlacing},
+    metadata::MetadataEdit,
+    reduction::alpha::AlphaFill,
+    strategies::FilterStrategy,
+};
 
 /// Write destination for [`optimize`][crate::optimize].
 /// You can use [`optimize_from_memory`](crate::optimize_from_memory) to avoid external I/O.
@@ -98,19 +108,48 @@ pub struct Options {
     ///
     /// Default: `None,Sub,Entropy,Bigrams`
     pub filter: IndexSet<RowFilter>,
+    /// Custom [`FilterStrategy`] implementations to try on the file, in addition to `filter`
+    ///
+    /// Default: `[]`
+    pub filter_strategies: Vec<Arc<dyn FilterStrategy>>,
+    /// A standard filter to use for each row of the output image, instead of trying any other
+    /// filters. For interlaced output, this includes the rows of every pass, in order.
+    ///
+    /// The number of rows must match the output image, otherwise optimization will fail. This
+    /// cannot be used together with `InterlaceMode::Auto`. Any additional APNG frames are not
+    /// recompressed.
+    ///
+    /// Default: `None`
+    pub row_filters: Option<Vec<RowFilter>>,
+    /// Maximum match distance for the cost model of the `BruteModel` filter, from 1 to 32768
+    ///
+    /// Default: `32768`
+    pub brute_window: usize,
+    /// Match search effort for the cost model of the `BruteModel` filter, from 1 to 9
+    ///
+    /// Default: `5`
+    pub brute_level: u8,
     /// Whether to change the interlacing type of the file.
     ///
-    /// These are the interlacing types avaliable:
-    /// - `None` will not change the current interlacing type.
-    /// - `Some(x)` will change the file to interlacing mode `x`.
+    /// These are the modes avaliable:
+    /// - `Keep` will not change the current interlacing type.
+    /// - `Set(x)` will change the file to interlacing type `x`.
     ///   See [`Interlacing`] for the possible interlacing types.
+    /// - `Auto { tolerance }` will evaluate both interlacing types and keep whichever is smaller.
     ///
-    /// Default: `Some(Interlacing::None)`
-    pub interlace: Option<Interlacing>,
+    /// Default: `Set(Interlacing::None)`
+    pub interlace: InterlaceMode,
     /// Whether to allow transparent pixels to be altered to improve compression.
     ///
     /// Default: `false`
     pub optimize_alpha: bool,
+    /// Which strategies to try for the color of fully transparent pixels, if `optimize_alpha` is
+    /// enabled. Each strategy is evaluated as a separate candidate.
+    ///
+    /// If empty, fully transparent pixels will be set to black.
+    ///
+    /// Default: `Predictor`
+    pub alpha_fill: IndexSet<AlphaFill>,
     /// Whether to attempt bit depth reduction
     ///
     /// Default: `true`
@@ -123,6 +162,11 @@ pub struct Options {
     ///
     /// Default: `true`
     pub palette_reduction: bool,
+    /// Whether to attempt refining the palette order with simulated annealing, if
+    /// `palette_reduction` is enabled. This is relatively slow.
+    ///
+    /// Default: `false`
+    pub palette_annealing: bool,
     /// Whether to attempt grayscale reduction
     ///
     /// Default: `true`
@@ -142,6 +186,34 @@ pub struct Options {
     ///
     /// Default: `None`
     pub strip: StripChunks,
+    /// Which text chunks to strip by keyword, taking precedence over `strip`
+    ///
+    /// Default: `None`
+    pub strip_text: StripText,
+    /// Which groups of tags to strip from the Exif data, if it is kept
+    ///
+    /// The output will be written even if it is larger than the input, so the tags are always
+    /// removed. If the Exif data can't be parsed, the eXIf chunk is removed instead.
+    ///
+    /// Default: `[]`
+    pub strip_exif: IndexSet<ExifGroup>,
+    /// Which properties to strip from the XMP data, if it is kept
+    ///
+    /// Properties are qualified names such as `xmpMM:History`, or `prefix:*` for all properties
+    /// of a namespace. The XMP data will be compressed if this makes it smaller.
+    ///
+    /// Default: `[]`
+    pub strip_xmp: IndexSet<String>,
+    /// Edits to make to the metadata of the PNG file, in order
+    ///
+    /// These are applied after chunks are removed by `strip`, so inserted chunks are not stripped,
+    /// but before any other processing: they are still subject to `strip_exif` and `strip_xmp`,
+    /// an iCCP chunk may be replaced with sRGB, and chunks that depend on the image format
+    /// may be updated or removed if it is reduced.
+    /// The output will be written even if it is larger than the input.
+    ///
+    /// Default: `[]`
+    pub metadata: Vec<MetadataEdit>,
     /// Which DEFLATE (zlib) algorithm to use
     #[cfg_attr(feature = "zopfli", doc = "(e.g. Zopfli)")]
     ///
@@ -151,6 +223,12 @@ pub struct Options {
     ///
     /// Default: `true`
     pub fast_evaluation: bool,
+    /// Whether to evaluate every reduction candidate with every selected filter and the main
+    /// deflater, rather than choosing reductions by a cheaper evaluation. This can be extremely
+    /// slow and is best combined with a `timeout`.
+    ///
+    /// Default: `false`
+    pub exhaustive: bool,
     /// Maximum amount of time to spend on optimizations.
     /// Further potential optimizations are skipped if the timeout is exceeded.
     ///
@@ -170,16 +248,18 @@ impl Options {
             4 => opts.apply_preset_4(),
             5 => opts.apply_preset_5(),
             6 => opts.apply_preset_6(),
+            7 => opts.apply_preset_7(),
             _ => {
-                warn!("Level 7 and above don't exist yet and are identical to level 6");
-                opts.apply_preset_6()
+                warn!("Level 8 and above don't exist yet and are identical to level 7");
+                opts.apply_preset_7()
             }
         }
     }
 
+    /// Options for the highest optimization preset, currently level 7
     #[must_use]
     pub fn max_compression() -> Self {
-        Self::from_preset(6)
+        Self::from_preset(7)
     }
 
     // The following methods make assumptions that they are operating
@@ -235,10 +315,16 @@ impl Options {
     }
 
     fn apply_preset_6(mut self) -> Self {
+        self.palette_annealing = true;
         self.filter.insert(RowFilter::Average);
         self.filter.insert(RowFilter::Paeth);
         self.apply_preset_5()
     }
+
+    fn apply_preset_7(mut self) -> Self {
+        self.filter.insert(RowFilter::Genetic);
+        self.apply_preset_6()
+    }
 }
 
 impl Default for Options {
@@ -248,17 +334,28 @@ impl Default for Options {
             fix_errors: false,
             force: false,
             filter: indexset! {RowFilter::None, RowFilter::Sub, RowFilter::Entropy, RowFilter::Bigrams},
-            interlace: Some(Interlacing::None),
+            filter_strategies: Vec::new(),
+            row_filters: None,
+            brute_window: 32768,
+            brute_level: 5,
+            interlace: InterlaceMode::Set(Interlacing::None),
             optimize_alpha: false,
+            alpha_fill: indexset! {AlphaFill::Predictor},
             bit_depth_reduction: true,
             color_type_reduction: true,
             palette_reduction: true,
+            palette_annealing: false,
             grayscale_reduction: true,
             idat_recoding: true,
             scale_16: false,
             strip: StripChunks::None,
+            strip_text: StripText::None,
+            strip_exif: IndexSet::new(),
+            strip_xmp: IndexSet::new(),
+            metadata: Vec::new(),
             deflate: Deflaters::Libdeflater { compression: 11 },
             fast_evaluation: true,
+            exhaustive: false,
             timeout: None,
         }
     }
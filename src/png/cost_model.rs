@@ -0,0 +1,293 @@
+//! Running LZ77/Huffman cost model, used by the BruteModel filter strategy
+//! Unlike the Brute strategy, each line is judged by its marginal cost given all prior data
+
+use crate::{filters::RowFilter, png::PngImage, Options};
+
+/// Hash table size for finding matches
+const HASH_BITS: usize = 15;
+/// Size of the deflate window
+const WINDOW_SIZE: usize = 1 << 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+/// Base values for length codes 257-285 (deflate spec section 3.2.5)
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// Base values for distance codes 0-29
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Maximum hash chain length to search for each level
+const MAX_CHAIN: [usize; 9] = [4, 8, 16, 32, 64, 128, 256, 1024, 4096];
+
+/// Settings for the BruteModel filter strategy
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ModelSettings {
+    /// Maximum distance of matches, up to 32768
+    pub window: usize,
+    /// Match search effort, from 1 to 9
+    pub level: u8,
+}
+
+impl Default for ModelSettings {
+    fn default() -> Self {
+        Self {
+            window: WINDOW_SIZE,
+            level: 5,
+        }
+    }
+}
+
+impl From<&Options> for ModelSettings {
+    fn from(opts: &Options) -> Self {
+        Self {
+            window: opts.brute_window,
+            level: opts.brute_level,
+        }
+    }
+}
+
+/// Estimates the compressed size of data as it is appended, one line at a time
+pub(crate) struct CostModel {
+    window: usize,
+    max_chain: usize,
+    /// All data committed so far
+    data: Vec<u8>,
+    /// Most recent position + 1 for each hash, or 0 if none
+    head: Vec<usize>,
+    /// Previous position + 1 with the same hash, for each position in the window
+    prev: Vec<usize>,
+    /// Symbol frequencies for literals/lengths and distances
+    lit_freq: [u32; 286],
+    dist_freq: [u32; 30],
+    /// Estimated cost in bits of each symbol
+    lit_cost: [f32; 286],
+    dist_cost: [f32; 30],
+    /// Hash table changes to be reverted after evaluating a line
+    undo: Vec<(bool, usize, usize)>,
+}
+
+impl CostModel {
+    #[must_use]
+    pub(crate) fn new(settings: ModelSettings, capacity: usize) -> Self {
+        let mut model = Self {
+            window: settings.window.clamp(1, WINDOW_SIZE),
+            max_chain: MAX_CHAIN[usize::from(settings.level.clamp(1, 9)) - 1],
+            data: Vec::with_capacity(capacity),
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; WINDOW_SIZE],
+            // Start with a count of 1 for every symbol so that unseen symbols have a finite cost
+            lit_freq: [1; 286],
+            dist_freq: [1; 30],
+            lit_cost: [0.0; 286],
+            dist_cost: [0.0; 30],
+            undo: Vec::new(),
+        };
+        model.update_costs();
+        model
+    }
+
+    fn update_costs(&mut self) {
+        fn costs(freq: &[u32], cost: &mut [f32]) {
+            let total = freq.iter().map(|&f| f as f32).sum::<f32>();
+            for (c, &f) in cost.iter_mut().zip(freq) {
+                *c = (total / f as f32).log2();
+            }
+        }
+        costs(&self.lit_freq, &mut self.lit_cost);
+        costs(&self.dist_freq, &mut self.dist_cost);
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let d = &self.data;
+        ((usize::from(d[pos]) << 10) ^ (usize::from(d[pos + 1]) << 5) ^ usize::from(d[pos + 2]))
+            & ((1 << HASH_BITS) - 1)
+    }
+
+    fn insert(&mut self, pos: usize, commit: bool) {
+        if pos + MIN_MATCH > self.data.len() {
+            return;
+        }
+        let h = self.hash(pos);
+        let slot = pos & (WINDOW_SIZE - 1);
+        if !commit {
+            self.undo.push((true, h, self.head[h]));
+            self.undo.push((false, slot, self.prev[slot]));
+        }
+        self.prev[slot] = self.head[h];
+        self.head[h] = pos + 1;
+    }
+
+    /// Find the longest match at `pos`, returning the length and distance
+    fn find_match(&self, pos: usize) -> (usize, usize) {
+        let end = self.data.len();
+        if pos + MIN_MATCH > end {
+            return (0, 0);
+        }
+        let max_len = (end - pos).min(MAX_MATCH);
+        let mut best = (0, 0);
+        let mut next = self.head[self.hash(pos)];
+        for _ in 0..self.max_chain {
+            // Stop if the chain is empty, out of the window, or has been overwritten by newer data
+            let Some(cand) = next
+                .checked_sub(1)
+                .filter(|&c| c < pos && pos - c <= self.window)
+            else {
+                break;
+            };
+            let len = self.data[cand..cand + max_len]
+                .iter()
+                .zip(&self.data[pos..pos + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best.0 {
+                best = (len, pos - cand);
+                if len == max_len {
+                    break;
+                }
+            }
+            let prev = self.prev[cand & (WINDOW_SIZE - 1)];
+            if prev >= next {
+                break;
+            }
+            next = prev;
+        }
+        if best.0 < MIN_MATCH {
+            (0, 0)
+        } else {
+            best
+        }
+    }
+
+    /// Parse the data from `start` to the end, returning the estimated cost in bits
+    /// If `commit` is set, the symbol frequencies are updated, otherwise the hash table is restored
+    fn parse(&mut self, start: usize, commit: bool) -> f32 {
+        let mut cost = 0.0;
+        // The last positions of the previous line could only be hashed once more data followed
+        for pos in start.saturating_sub(MIN_MATCH - 1)..start {
+            self.insert(pos, commit);
+        }
+        let mut pos = start;
+        while pos < self.data.len() {
+            let (len, dist) = self.find_match(pos);
+            if len == 0 {
+                let lit = usize::from(self.data[pos]);
+                cost += self.lit_cost[lit];
+                if commit {
+                    self.lit_freq[lit] += 1;
+                }
+                self.insert(pos, commit);
+                pos += 1;
+            } else {
+                let l = LENGTH_BASE.partition_point(|&b| usize::from(b) <= len) - 1;
+                let d = DIST_BASE.partition_point(|&b| usize::from(b) <= dist) - 1;
+                cost += self.lit_cost[257 + l]
+                    + f32::from(LENGTH_EXTRA[l])
+                    + self.dist_cost[d]
+                    + f32::from(DIST_EXTRA[d]);
+                if commit {
+                    self.lit_freq[257 + l] += 1;
+                    self.dist_freq[d] += 1;
+                }
+                for p in pos..pos + len {
+                    self.insert(p, commit);
+                }
+                pos += len;
+            }
+        }
+        if commit {
+            self.update_costs();
+        } else {
+            while let Some((is_head, idx, val)) = self.undo.pop() {
+                if is_head {
+                    self.head[idx] = val;
+                } else {
+                    self.prev[idx] = val;
+                }
+            }
+        }
+        cost
+    }
+
+    /// Estimate the marginal cost of appending the line
+    pub(crate) fn cost(&mut self, line: &[u8]) -> f32 {
+        let start = self.data.len();
+        self.data.extend_from_slice(line);
+        let cost = self.parse(start, false);
+        self.data.truncate(start);
+        cost
+    }
+
+    /// Append the line to the model
+    pub(crate) fn commit(&mut self, line: &[u8]) {
+        let start = self.data.len();
+        self.data.extend_from_slice(line);
+        self.parse(start, true);
+    }
+}
+
+/// Estimate the marginal cost of appending a line after the committed lines, using the default
+/// model settings
+#[must_use]
+pub fn estimate_line_cost(committed: &[&[u8]], line: &[u8]) -> f32 {
+    let capacity = committed.iter().map(|l| l.len()).sum::<usize>() + line.len();
+    let mut model = CostModel::new(ModelSettings::default(), capacity);
+    for l in committed {
+        model.commit(l);
+    }
+    model.cost(line)
+}
+
+impl PngImage {
+    /// Choose the filter for each line with the lowest marginal cost in a running compression model
+    pub(crate) fn filter_image_model(
+        &self,
+        optimize_alpha: bool,
+        settings: ModelSettings,
+    ) -> Vec<u8> {
+        let (bpp, alpha_bytes) = self.filter_params(optimize_alpha);
+        let mut model = CostModel::new(settings, self.data.len() + self.ihdr.height as usize);
+        let mut prev_line = Vec::new();
+        let mut prev_pass: Option<u8> = None;
+        let mut f_buf = Vec::new();
+        let mut best_line = Vec::new();
+        let mut best_line_raw = Vec::new();
+        for line in self.scan_lines(false) {
+            if prev_pass != line.pass || line.data.len() != prev_line.len() {
+                prev_line = vec![0; line.data.len()];
+            }
+            // Avoid vertical filtering on first line of each interlacing pass
+            let try_filters = if prev_pass == line.pass {
+                RowFilter::STANDARD.iter()
+            } else {
+                RowFilter::SINGLE_LINE.iter()
+            };
+            let mut best_cost = f32::MAX;
+            for f in try_filters {
+                // Alpha optimisation may alter the line data, so we need a mutable copy of it
+                let mut line_data = line.data.to_vec();
+                f.filter_line(bpp, &mut line_data, &prev_line, &mut f_buf, alpha_bytes);
+                let cost = model.cost(&f_buf);
+                if cost < best_cost {
+                    best_cost = cost;
+                    std::mem::swap(&mut best_line, &mut f_buf);
+                    best_line_raw = line_data;
+                }
+            }
+            model.commit(&best_line);
+            std::mem::swap(&mut prev_line, &mut best_line_raw);
+            prev_pass = line.pass;
+        }
+        model.data
+    }
+}
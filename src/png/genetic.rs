@@ -0,0 +1,152 @@
+//! Genetic search for the best filter of each line, in the spirit of pngwolf
+//! https://bjoern.hoehrmann.de/pngwolf/
+
+use libdeflater::{CompressionLvl, Compressor};
+use rayon::prelude::*;
+
+#[cfg(not(feature = "parallel"))]
+use crate::rayon;
+use crate::{
+    filters::{Filter, RowFilter},
+    png::{ModelSettings, PngImage},
+    rng::XorShift,
+    Deadline,
+};
+
+/// Compression level used to score each genome
+const GENETIC_LEVEL: i32 = 5; // Lower levels are too inaccurate, higher levels are too slow
+/// Number of genomes kept in each generation
+const POPULATION: usize = 16;
+/// Maximum number of generations to evolve
+const MAX_GENERATIONS: usize = 200;
+/// Stop evolving after this many generations without improvement
+const MAX_STAGNANT: usize = 20;
+
+/// A filter for each line, together with its compressed size
+struct Genome {
+    filters: Vec<RowFilter>,
+    size: usize,
+}
+
+impl PngImage {
+    /// Evolve a set of per-line filters, starting from the standard filters and heuristic strategies
+    /// Evolution continues until no further improvement is found or the deadline has passed
+    pub(crate) fn evolve_filters(
+        &self,
+        optimize_alpha: bool,
+        deadline: &Deadline,
+        model: ModelSettings,
+    ) -> Vec<u8> {
+        let line_lens: Vec<_> = self.scan_lines(false).map(|l| l.data.len() + 1).collect();
+
+        // Seed the population with the results of each of the other strategies
+        let seeds: Vec<_> = RowFilter::STANDARD
+            .iter()
+            .chain(&RowFilter::HEURISTIC)
+            .copied()
+            .collect();
+        let mut population: Vec<Genome> = seeds
+            .par_iter()
+            .with_max_len(1)
+            .map(|&filter| {
+                let filtered =
+                    self.filter_image_with(&Filter::Row(filter), optimize_alpha, deadline, model);
+                let mut pos = 0;
+                let filters = line_lens
+                    .iter()
+                    .map(|len| {
+                        let filter = RowFilter::try_from(filtered[pos]).unwrap_or(RowFilter::None);
+                        pos += len;
+                        filter
+                    })
+                    .collect();
+                Genome {
+                    filters,
+                    size: compressed_size(&filtered),
+                }
+            })
+            .collect();
+        sort_and_dedup(&mut population);
+
+        let mut rng = XorShift::new(0x2545_F491);
+        let mut stagnant = 0;
+        for _ in 0..MAX_GENERATIONS {
+            if stagnant >= MAX_STAGNANT || line_lens.len() < 2 || deadline.passed() {
+                break;
+            }
+
+            // Breed a new generation from the fittest genomes
+            let children: Vec<_> = (0..POPULATION)
+                .map(|_| {
+                    let a = &population[tournament(&mut rng, population.len())].filters;
+                    let b = &population[tournament(&mut rng, population.len())].filters;
+                    let mut child = crossover(&mut rng, a, b);
+                    mutate(&mut rng, &mut child);
+                    child
+                })
+                .collect();
+            let children: Vec<_> = children
+                .into_par_iter()
+                .with_max_len(1)
+                .map(|filters| {
+                    let size = compressed_size(&self.filter_lines(&filters, optimize_alpha));
+                    Genome { filters, size }
+                })
+                .collect();
+
+            let best_size = population[0].size;
+            population.extend(children);
+            sort_and_dedup(&mut population);
+            population.truncate(POPULATION);
+            if population[0].size < best_size {
+                stagnant = 0;
+            } else {
+                stagnant += 1;
+            }
+        }
+
+        self.filter_lines(&population[0].filters, optimize_alpha)
+    }
+}
+
+/// Sort genomes by size, removing any duplicates
+fn sort_and_dedup(population: &mut Vec<Genome>) {
+    population.sort_by_key(|g| g.size);
+    population.dedup_by(|a, b| a.size == b.size && a.filters == b.filters);
+}
+
+/// Pick the fitter of two random genomes, returning its index
+fn tournament(rng: &mut XorShift, len: usize) -> usize {
+    // The population is sorted, so the lower index is the fitter one
+    rng.below(len).min(rng.below(len))
+}
+
+/// Combine two genomes by taking a random range of lines from the second
+fn crossover(rng: &mut XorShift, a: &[RowFilter], b: &[RowFilter]) -> Vec<RowFilter> {
+    let mut start = rng.below(a.len());
+    let mut end = rng.below(a.len());
+    if start > end {
+        std::mem::swap(&mut start, &mut end);
+    }
+    let mut child = a.to_vec();
+    child[start..=end].copy_from_slice(&b[start..=end]);
+    child
+}
+
+/// Change the filters of one or two random lines
+fn mutate(rng: &mut XorShift, filters: &mut [RowFilter]) {
+    let count = 1 + rng.below(2);
+    for _ in 0..count {
+        let line = rng.below(filters.len());
+        filters[line] = RowFilter::STANDARD[rng.below(RowFilter::STANDARD.len())];
+    }
+}
+
+/// Compress the filtered data to determine its fitness
+fn compressed_size(filtered: &[u8]) -> usize {
+    let mut compressor = Compressor::new(CompressionLvl::new(GENETIC_LEVEL).unwrap());
+    let mut dest = vec![0; compressor.deflate_compress_bound(filtered.len())];
+    compressor
+        .deflate_compress(filtered, &mut dest)
+        .unwrap_or(usize::MAX)
+}
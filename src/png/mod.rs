@@ -5,8 +5,6 @@ use std::{
     sync::Arc,
 };
 
-use bitvec::bitarr;
-use libdeflater::{CompressionLvl, Compressor};
 use log::warn;
 use rgb::ComponentSlice;
 use rustc_hash::FxHashMap;
@@ -19,18 +17,18 @@ use crate::{
     filters::*,
     headers::*,
     interlace::{deinterlace_image, interlace_image, Interlacing},
-    Options,
+    strategies::{FilterStrategy, LineContext},
+    Deadline, Options,
 };
 
+mod cost_model;
+mod genetic;
 pub(crate) mod scan_lines;
 
+pub use self::cost_model::estimate_line_cost;
+pub(crate) use self::cost_model::ModelSettings;
 use self::scan_lines::ScanLines;
 
-/// Compression level to use for the Brute filter strategy
-const BRUTE_LEVEL: i32 = 1; // 1 is fastest, 2-4 are not useful, 5 is slower but more effective
-/// Number of lines to compress with the Brute filter strategy
-const BRUTE_LINES: usize = 4; // Values over 8 are generally not useful
-
 #[derive(Debug, Clone)]
 pub struct PngImage {
     /// The headers stored in the IHDR chunk
@@ -46,6 +44,8 @@ pub struct PngData {
     pub raw: Arc<PngImage>,
     /// The filtered and compressed data of the IDAT chunk
     pub idat_data: Vec<u8>,
+    /// The filter type of each line of the IDAT data, as read from the file
+    pub filters: Vec<RowFilter>,
     /// All non-critical chunks from the PNG are stored here
     pub aux_chunks: Vec<Chunk>,
     /// APNG frames
@@ -100,8 +100,9 @@ impl PngData {
         let mut idat_data: Vec<u8> = Vec::new();
         let mut key_chunks: FxHashMap<[u8; 4], Vec<u8>> = FxHashMap::default();
         let mut aux_chunks: Vec<Chunk> = Vec::new();
-        let mut frames: Vec<Frame> = Vec::new();
         let mut sequence_number = 0;
+        let mut renumbering = false;
+        let mut animation_chunks = Vec::new();
         while let Some(chunk) = parse_next_chunk(byte_data, &mut byte_offset, opts.fix_errors)? {
             match &chunk.name {
                 b"IDAT" => {
@@ -117,7 +118,7 @@ impl PngData {
                 b"IHDR" | b"PLTE" | b"tRNS" => {
                     key_chunks.insert(chunk.name, chunk.data.to_owned());
                 }
-                _ if opts.strip.keep(&chunk.name) => {
+                _ if keep_chunk(&chunk.name, chunk.data, opts) => {
                     if chunk.is_c2pa() {
                         // StripChunks::None is the default value, so to keep optimizing by default,
                         // interpret it as stripping the C2PA metadata.
@@ -129,21 +130,26 @@ impl PngData {
                     }
                     if chunk.name == *b"fcTL" || chunk.name == *b"fdAT" {
                         // Validate the sequence number
-                        if read_be_u32(&chunk.data[0..4]) != sequence_number {
-                            return Err(PngError::APNGOutOfOrder);
+                        let number = chunk.data.get(0..4).ok_or(PngError::TruncatedData)?;
+                        if read_be_u32(number) != sequence_number {
+                            if !opts.fix_errors {
+                                return Err(PngError::APNGOutOfOrder);
+                            }
+                            if !renumbering {
+                                warn!("APNG sequence numbers are out of order, reordering");
+                                renumbering = true;
+                            }
                         }
                         sequence_number += 1;
-                        if chunk.name == *b"fcTL" && !idat_data.is_empty() {
-                            // Only create a Frame if it's after the IDAT (else store it as an aux chunk)
-                            frames.push(Frame::from_fctl_data(chunk.data)?);
+                        if !idat_data.is_empty() {
+                            // Frames after the IDAT are assembled once all chunks are read
+                            animation_chunks.push(chunk);
                             continue;
                         } else if chunk.name == *b"fdAT" {
-                            // Append the data to the last frame
-                            frames
-                                .last_mut()
-                                .ok_or(PngError::APNGOutOfOrder)?
-                                .data
-                                .extend_from_slice(&chunk.data[4..]);
+                            if !opts.fix_errors {
+                                return Err(PngError::APNGOutOfOrder);
+                            }
+                            warn!("APNG fdAT chunk has no frame, removing it");
                             continue;
                         }
                     }
@@ -160,6 +166,34 @@ impl PngData {
             }
         }
 
+        // Restore the order of the frames, which is only possible if the sequence numbers are unique
+        if renumbering {
+            let mut numbers: Vec<_> = animation_chunks
+                .iter()
+                .map(|c| read_be_u32(&c.data[0..4]))
+                .collect();
+            numbers.sort_unstable();
+            if numbers.windows(2).any(|w| w[0] == w[1]) {
+                return Err(PngError::APNGOutOfOrder);
+            }
+            animation_chunks.sort_by_key(|c| read_be_u32(&c.data[0..4]));
+        }
+        let mut frames: Vec<Frame> = Vec::new();
+        for chunk in animation_chunks {
+            if chunk.name == *b"fcTL" {
+                frames.push(Frame::from_fctl_data(chunk.data)?);
+                continue;
+            }
+            // Append the data to the last frame
+            match frames.last_mut() {
+                Some(frame) => frame.data.extend_from_slice(&chunk.data[4..]),
+                None if opts.fix_errors => {
+                    warn!("APNG fdAT chunk has no frame, removing it");
+                }
+                None => return Err(PngError::APNGOutOfOrder),
+            }
+        }
+
         // Parse the chunks into our PngData
         if idat_data.is_empty() {
             return Err(PngError::ChunkMissing("IDAT"));
@@ -174,11 +208,14 @@ impl PngData {
             key_chunks.remove(b"tRNS"),
         )?;
 
-        let raw = PngImage::new(ihdr, &idat_data)?;
+        validate_animation(&mut aux_chunks, &mut frames, &ihdr, opts.fix_errors)?;
+
+        let (raw, filters) = PngImage::new_with_filters(ihdr, &idat_data)?;
 
         // Return the PngData
         Ok(Self {
             idat_data,
+            filters,
             raw: Arc::new(raw),
             aux_chunks,
             frames,
@@ -207,6 +244,18 @@ impl PngData {
         // Ancillary chunks - split into those that come before IDAT and those that come after
         let mut aux_split = self.aux_chunks.split(|c| &c.name == b"IDAT");
         let aux_pre = aux_split.next().unwrap();
+        // Frames may have been merged, so the frame count of acTL and the sequence numbers of fcTL
+        // are rewritten, both of which are stored in the first 4 bytes of the chunk
+        let default_frames = aux_pre.iter().filter(|c| &c.name == b"fcTL").count();
+        let num_frames = (self.frames.len() + default_frames) as u32;
+        let mut sequence_number = 0u32;
+        let with_number = |chunk: &Chunk, number: u32| {
+            let mut data = chunk.data.clone();
+            if let Some(prefix) = data.get_mut(0..4) {
+                prefix.copy_from_slice(&number.to_be_bytes());
+            }
+            data
+        };
         // Many chunks need to be before PLTE, so write all except those that explicitly need to be after
         // Note: the PNG spec does not say that fcTL needs to be after PLTE, but some decoders expect
         //       that (see issue #625)
@@ -214,7 +263,11 @@ impl PngData {
             .iter()
             .filter(|c| !matches!(&c.name, b"bKGD" | b"hIST" | b"tRNS" | b"fcTL"))
         {
-            write_png_block(&chunk.name, &chunk.data, &mut output);
+            if &chunk.name == b"acTL" {
+                write_png_block(b"acTL", &with_number(chunk, num_frames), &mut output);
+            } else {
+                write_png_block(&chunk.name, &chunk.data, &mut output);
+            }
         }
         // Palette and transparency
         match &self.raw.ihdr.color_type {
@@ -245,14 +298,16 @@ impl PngData {
             _ => {}
         }
         // Special ancillary chunks that need to come after PLTE but before IDAT
-        let mut sequence_number = 0;
         for chunk in aux_pre
             .iter()
             .filter(|c| matches!(&c.name, b"bKGD" | b"hIST" | b"tRNS" | b"fcTL"))
         {
-            write_png_block(&chunk.name, &chunk.data, &mut output);
             if &chunk.name == b"fcTL" {
+                let data = with_number(chunk, sequence_number);
+                write_png_block(b"fcTL", &data, &mut output);
                 sequence_number += 1;
+            } else {
+                write_png_block(&chunk.name, &chunk.data, &mut output);
             }
         }
         // IDAT data
@@ -278,6 +333,14 @@ impl PngData {
 
 impl PngImage {
     pub fn new(ihdr: IhdrData, compressed_data: &[u8]) -> Result<Self, PngError> {
+        Self::new_with_filters(ihdr, compressed_data).map(|(image, _)| image)
+    }
+
+    /// Construct the image from compressed data, also returning the filter type of each line
+    pub fn new_with_filters(
+        ihdr: IhdrData,
+        compressed_data: &[u8],
+    ) -> Result<(Self, Vec<RowFilter>), PngError> {
         let raw_data = deflate::inflate(compressed_data, ihdr.raw_data_size())?;
 
         // Reject files with incorrect width/height or truncated data
@@ -289,8 +352,9 @@ impl PngImage {
             ihdr,
             data: raw_data,
         };
-        image.data = image.unfilter_image()?;
-        Ok(image)
+        let (data, filters) = image.unfilter_image()?;
+        image.data = data;
+        Ok((image, filters))
     }
 
     /// Convert the image to the specified interlacing type
@@ -363,8 +427,10 @@ impl PngImage {
     }
 
     /// Reverse all filters applied on the image, returning an unfiltered IDAT bytestream
-    fn unfilter_image(&self) -> Result<Vec<u8>, PngError> {
+    /// along with the filter type of each line
+    fn unfilter_image(&self) -> Result<(Vec<u8>, Vec<RowFilter>), PngError> {
         let mut unfiltered = Vec::with_capacity(self.data.len());
+        let mut filters = Vec::new();
         let bpp = self.bytes_per_channel() * self.channels_per_pixel();
         let mut last_line: Vec<u8> = Vec::new();
         let mut last_pass = None;
@@ -378,23 +444,104 @@ impl PngImage {
             let filter = RowFilter::try_from(line.filter).map_err(|_| PngError::InvalidData)?;
             filter.unfilter_line(bpp, line.data, &last_line, &mut unfiltered_buf)?;
             unfiltered.extend_from_slice(&unfiltered_buf);
+            filters.push(filter);
             std::mem::swap(&mut last_line, &mut unfiltered_buf);
             unfiltered_buf.clear();
         }
-        Ok(unfiltered)
+        Ok((unfiltered, filters))
     }
 
-    /// Apply the specified filter type to all rows in the image
-    #[must_use]
-    pub fn filter_image(&self, filter: RowFilter, optimize_alpha: bool) -> Vec<u8> {
-        let mut filtered = Vec::with_capacity(self.data.len());
+    /// Determine the bytes per pixel and, if alpha optimization is enabled, the bytes of alpha per pixel
+    fn filter_params(&self, optimize_alpha: bool) -> (usize, usize) {
         let bpp = self.bytes_per_channel() * self.channels_per_pixel();
-        // If alpha optimization is enabled, determine how many bytes of alpha there are per pixel
         let alpha_bytes = if optimize_alpha && self.ihdr.color_type.has_alpha() {
             self.bytes_per_channel()
         } else {
             0
         };
+        (bpp, alpha_bytes)
+    }
+
+    /// Apply the specified filter to all rows in the image, using the given settings for the
+    /// BruteModel strategy and stopping any search strategy once the deadline has passed
+    #[must_use]
+    pub(crate) fn filter_image_with(
+        &self,
+        filter: &Filter,
+        optimize_alpha: bool,
+        deadline: &Deadline,
+        model: ModelSettings,
+    ) -> Vec<u8> {
+        match *filter {
+            Filter::Row(RowFilter::Genetic) => self.evolve_filters(optimize_alpha, deadline, model),
+            Filter::Row(RowFilter::BruteModel) => self.filter_image_model(optimize_alpha, model),
+            Filter::Row(filter) => {
+                self.filter_image_with_deadline(filter, optimize_alpha, deadline)
+            }
+            Filter::Custom(_, ref strategy) => {
+                self.filter_image_strategy(strategy.as_ref(), optimize_alpha)
+            }
+            Filter::Rows(ref filters) | Filter::Original(ref filters) => {
+                self.filter_lines(filters, optimize_alpha)
+            }
+        }
+    }
+
+    /// Apply a standard filter type to each row in the image, with one filter given per row
+    #[must_use]
+    pub(crate) fn filter_lines(&self, filters: &[RowFilter], optimize_alpha: bool) -> Vec<u8> {
+        let mut filtered = Vec::with_capacity(self.data.len() + filters.len());
+        let (bpp, alpha_bytes) = self.filter_params(optimize_alpha);
+        let mut prev_line = Vec::new();
+        let mut prev_pass: Option<u8> = None;
+        let mut f_buf = Vec::new();
+        for (line, filter) in self.scan_lines(false).zip(filters) {
+            if prev_pass != line.pass || line.data.len() != prev_line.len() {
+                prev_line = vec![0; line.data.len()];
+            }
+            let mut line_data = line.data.to_vec();
+            filter.filter_line(bpp, &mut line_data, &prev_line, &mut f_buf, alpha_bytes);
+            filtered.extend_from_slice(&f_buf);
+            prev_line = line_data;
+            prev_pass = line.pass;
+        }
+        filtered
+    }
+
+    /// Apply the specified filter type to all rows in the image
+    ///
+    /// The `BruteModel` and `Genetic` strategies use the default cost model settings, ignoring
+    /// `Options::brute_window` and `Options::brute_level`, and the search is not time limited.
+    #[must_use]
+    pub fn filter_image(&self, filter: RowFilter, optimize_alpha: bool) -> Vec<u8> {
+        self.filter_image_with_deadline(filter, optimize_alpha, &Deadline::new(None))
+    }
+
+    /// Apply the specified filter type to all rows in the image, stopping any search strategy once
+    /// the deadline has passed
+    #[must_use]
+    pub(crate) fn filter_image_with_deadline(
+        &self,
+        filter: RowFilter,
+        optimize_alpha: bool,
+        deadline: &Deadline,
+    ) -> Vec<u8> {
+        match filter {
+            RowFilter::Genetic => {
+                return self.evolve_filters(optimize_alpha, deadline, ModelSettings::default())
+            }
+            RowFilter::BruteModel => {
+                return self.filter_image_model(optimize_alpha, ModelSettings::default())
+            }
+            _ => {}
+        }
+        if let Some(strategy) = filter.strategy() {
+            return self.filter_image_by(strategy, optimize_alpha, true);
+        }
+
+        let mut filtered = Vec::with_capacity(self.data.len());
+        // If alpha optimization is enabled, determine how many bytes of alpha there are per pixel
+        let (bpp, alpha_bytes) = self.filter_params(optimize_alpha);
 
         let mut prev_line = Vec::new();
         let mut prev_pass: Option<u8> = None;
@@ -406,147 +553,86 @@ impl PngImage {
             // Alpha optimisation may alter the line data, so we need a mutable copy of it
             let mut line_data = line.data.to_vec();
 
-            if filter <= RowFilter::Paeth {
-                // Standard filters
-                let filter = if prev_pass == line.pass || filter <= RowFilter::Sub {
-                    filter
-                } else {
-                    RowFilter::None
-                };
-                filter.filter_line(bpp, &mut line_data, &prev_line, &mut f_buf, alpha_bytes);
-                filtered.extend_from_slice(&f_buf);
-                prev_line = line_data;
+            // Standard filters
+            let filter = if prev_pass == line.pass || filter <= RowFilter::Sub {
+                filter
             } else {
-                // Heuristic filter selection strategies
-
-                if line_data.iter().all(|&x| x == 0) {
-                    // Assume None if the line is all zeros
-                    filtered.push(RowFilter::None as u8);
-                    filtered.extend_from_slice(&line_data);
-                    prev_line = line_data;
-                    continue;
-                }
+                RowFilter::None
+            };
+            filter.filter_line(bpp, &mut line_data, &prev_line, &mut f_buf, alpha_bytes);
+            filtered.extend_from_slice(&f_buf);
+            prev_line = line_data;
+            prev_pass = line.pass;
+        }
+        filtered
+    }
 
-                let mut best_line = Vec::new();
-                let mut best_line_raw = Vec::new();
-                // Avoid vertical filtering on first line of each interlacing pass
-                let try_filters = if prev_pass == line.pass {
-                    RowFilter::STANDARD.iter()
-                } else {
-                    RowFilter::SINGLE_LINE.iter()
-                };
-                match filter {
-                    RowFilter::MinSum => {
-                        // MSAD algorithm mentioned in libpng reference docs
-                        // http://www.libpng.org/pub/png/book/chapter09.html
-                        let mut best_size = usize::MAX;
-                        for f in try_filters {
-                            f.filter_line(bpp, &mut line_data, &prev_line, &mut f_buf, alpha_bytes);
-                            let size = f_buf.iter().fold(0, |acc, &x| {
-                                let signed = x as i8;
-                                acc + signed.unsigned_abs() as usize
-                            });
-                            if size < best_size {
-                                best_size = size;
-                                std::mem::swap(&mut best_line, &mut f_buf);
-                                best_line_raw.clone_from(&line_data);
-                            }
-                        }
-                    }
-                    RowFilter::Entropy => {
-                        // Shannon entropy algorithm, from LodePNG
-                        // https://github.com/lvandeve/lodepng
-                        let mut best_size = i32::MIN;
-                        for f in try_filters {
-                            f.filter_line(bpp, &mut line_data, &prev_line, &mut f_buf, alpha_bytes);
-                            let mut counts = vec![0; 0x100];
-                            for &i in &f_buf {
-                                counts[i as usize] += 1;
-                            }
-                            let size = counts.into_iter().fold(0, |acc, x| {
-                                if x == 0 {
-                                    return acc;
-                                }
-                                acc + ilog2i(x)
-                            }) as i32;
-                            if size > best_size {
-                                best_size = size;
-                                std::mem::swap(&mut best_line, &mut f_buf);
-                                best_line_raw.clone_from(&line_data);
-                            }
-                        }
-                    }
-                    RowFilter::Bigrams => {
-                        // Count distinct bigrams, from pngwolf
-                        // https://bjoern.hoehrmann.de/pngwolf/
-                        let mut best_size = usize::MAX;
-                        for f in try_filters {
-                            f.filter_line(bpp, &mut line_data, &prev_line, &mut f_buf, alpha_bytes);
-                            let mut set = bitarr![0; 0x10000];
-                            for pair in f_buf.windows(2) {
-                                let bigram = ((pair[0] as usize) << 8) | pair[1] as usize;
-                                set.set(bigram, true);
-                            }
-                            let size = set.count_ones();
-                            if size < best_size {
-                                best_size = size;
-                                std::mem::swap(&mut best_line, &mut f_buf);
-                                best_line_raw.clone_from(&line_data);
-                            }
-                        }
-                    }
-                    RowFilter::BigEnt => {
-                        // Bigram entropy, combined from Entropy and Bigrams filters
-                        let mut best_size = i32::MIN;
-                        // FxHasher is the fastest rust hasher currently available for this purpose
-                        let mut counts = FxHashMap::<u16, u32>::default();
-                        for f in try_filters {
-                            f.filter_line(bpp, &mut line_data, &prev_line, &mut f_buf, alpha_bytes);
-                            counts.clear();
-                            for pair in f_buf.windows(2) {
-                                let bigram = (u16::from(pair[0]) << 8) | u16::from(pair[1]);
-                                counts.entry(bigram).and_modify(|e| *e += 1).or_insert(1);
-                            }
-                            let size = counts.values().fold(0, |acc, &x| acc + ilog2i(x)) as i32;
-                            if size > best_size {
-                                best_size = size;
-                                std::mem::swap(&mut best_line, &mut f_buf);
-                                best_line_raw.clone_from(&line_data);
-                            }
-                        }
-                    }
-                    RowFilter::Brute => {
-                        // Brute force by compressing each filter attempt
-                        // Similar to that of LodePNG but includes some previous lines for context
-                        let mut best_size = usize::MAX;
-                        let line_start = filtered.len();
-                        filtered.resize(filtered.len() + line.data.len() + 1, 0);
-                        let mut compressor =
-                            Compressor::new(CompressionLvl::new(BRUTE_LEVEL).unwrap());
-                        let limit = filtered.len().min((line.data.len() + 1) * BRUTE_LINES);
-                        let capacity = compressor.zlib_compress_bound(limit);
-                        let mut dest = vec![0; capacity];
-
-                        for f in try_filters {
-                            f.filter_line(bpp, &mut line_data, &prev_line, &mut f_buf, alpha_bytes);
-                            filtered[line_start..].copy_from_slice(&f_buf);
-                            let size = compressor
-                                .zlib_compress(&filtered[filtered.len() - limit..], &mut dest)
-                                .unwrap_or(usize::MAX);
-                            if size < best_size {
-                                best_size = size;
-                                std::mem::swap(&mut best_line, &mut f_buf);
-                                best_line_raw.clone_from(&line_data);
-                            }
-                        }
-                        filtered.resize(line_start, 0);
-                    }
-                    _ => unreachable!(),
-                }
-                filtered.extend_from_slice(&best_line);
-                prev_line = best_line_raw;
+    /// Choose the filter of each row in the image using the given strategy
+    #[must_use]
+    pub fn filter_image_strategy(
+        &self,
+        strategy: &dyn FilterStrategy,
+        optimize_alpha: bool,
+    ) -> Vec<u8> {
+        self.filter_image_by(strategy, optimize_alpha, false)
+    }
+
+    /// Choose the filter of each row using the given strategy, optionally assuming None for any
+    /// line that is all zeros
+    fn filter_image_by(
+        &self,
+        strategy: &dyn FilterStrategy,
+        optimize_alpha: bool,
+        skip_zeros: bool,
+    ) -> Vec<u8> {
+        let mut filtered = Vec::with_capacity(self.data.len());
+        let (bpp, alpha_bytes) = self.filter_params(optimize_alpha);
+
+        let mut prev_line = Vec::new();
+        let mut prev_pass: Option<u8> = None;
+        let mut lines = vec![Vec::new(); RowFilter::STANDARD.len()];
+        let mut lines_raw = vec![Vec::new(); RowFilter::STANDARD.len()];
+        for (index, line) in self.scan_lines(false).enumerate() {
+            if prev_pass != line.pass || line.data.len() != prev_line.len() {
+                prev_line = vec![0; line.data.len()];
             }
 
+            if skip_zeros && line.data.iter().all(|&x| x == 0) {
+                // Assume None if the line is all zeros
+                filtered.push(RowFilter::None as u8);
+                filtered.extend_from_slice(line.data);
+                prev_line = line.data.to_vec();
+                prev_pass = line.pass;
+                continue;
+            }
+
+            // Avoid vertical filtering on first line of each interlacing pass
+            let try_filters: &[RowFilter] = if prev_pass == line.pass {
+                &RowFilter::STANDARD
+            } else {
+                &RowFilter::SINGLE_LINE
+            };
+            for ((f, f_buf), line_raw) in try_filters.iter().zip(&mut lines).zip(&mut lines_raw) {
+                // Alpha optimisation may alter the line data, so we need a mutable copy of it
+                line_raw.clear();
+                line_raw.extend_from_slice(line.data);
+                f.filter_line(bpp, line_raw, &prev_line, f_buf, alpha_bytes);
+            }
+            let candidates: Vec<&[u8]> = lines[..try_filters.len()]
+                .iter()
+                .map(Vec::as_slice)
+                .collect();
+            let context = LineContext {
+                index,
+                pass: line.pass,
+                bpp,
+                prev_line: &prev_line,
+                filtered: &filtered,
+            };
+            let best = strategy.choose(&candidates, &context);
+            let best = best.min(candidates.len() - 1);
+            filtered.extend_from_slice(candidates[best]);
+            std::mem::swap(&mut prev_line, &mut lines_raw[best]);
             prev_pass = line.pass;
         }
         filtered
@@ -563,9 +649,3 @@ fn write_png_block(key: &[u8], chunk: &[u8], output: &mut Vec<u8>) {
     output.append(&mut chunk_data);
     output.extend_from_slice(&crc.to_be_bytes());
 }
-
-// Integer approximation for i * log2(i) - much faster than float calculations
-const fn ilog2i(i: u32) -> u32 {
-    let log = 32 - i.leading_zeros() - 1;
-    i * log + ((i - (1 << log)) << 1)
-}
@@ -1,11 +1,44 @@
+use std::{collections::VecDeque, fmt, fmt::Display};
+
 use rgb::RGB16;
 
 use crate::{
     colors::{BitDepth, ColorType},
     headers::IhdrData,
+    interlace::{deinterlace_image, interlace_image, Interlacing},
     png::PngImage,
 };
 
+/// Strategy for choosing the color of fully transparent pixels, for use in [`Options`][crate::Options]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub enum AlphaFill {
+    /// Set the color to black
+    Black,
+    /// Copy the color of the pixel to the left
+    Left,
+    /// Copy the color of the pixel above
+    Up,
+    /// Set the color to match the prediction of the delta filter applied to each line
+    Predictor,
+    /// Copy the color of the nearest opaque pixel
+    Nearest,
+}
+
+impl Display for AlphaFill {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(
+            match self {
+                Self::Black => "Black",
+                Self::Left => "Left",
+                Self::Up => "Up",
+                Self::Predictor => "Predictor",
+                Self::Nearest => "Nearest",
+            },
+            f,
+        )
+    }
+}
+
 /// Clean the alpha channel by setting the color of all fully transparent pixels to black
 #[must_use]
 pub fn cleaned_alpha_channel(png: &PngImage) -> Option<PngImage> {
@@ -31,6 +64,109 @@ pub fn cleaned_alpha_channel(png: &PngImage) -> Option<PngImage> {
     })
 }
 
+/// Fill the color of all fully transparent pixels using the given strategy, returning the new image
+/// if anything changed
+///
+/// The `Predictor` strategy depends on the filter and is applied during filtering instead, so here it
+/// is equivalent to `Black`.
+#[must_use]
+pub fn filled_alpha_channel(png: &PngImage, fill: AlphaFill) -> Option<PngImage> {
+    if !png.ihdr.color_type.has_alpha() {
+        return None;
+    }
+    let byte_depth = png.bytes_per_channel();
+    let bpp = png.channels_per_pixel() * byte_depth;
+    let colored_bytes = bpp - byte_depth;
+    let is_transparent = |pixel: &[u8]| pixel.iter().skip(colored_bytes).all(|b| *b == 0);
+
+    let filled = match fill {
+        AlphaFill::Black | AlphaFill::Predictor => cleaned_alpha_channel(png)?,
+        AlphaFill::Left | AlphaFill::Up => {
+            let mut data = Vec::with_capacity(png.data.len());
+            let mut prev_line: Option<(usize, Option<u8>)> = None;
+            for line in png.scan_lines(false) {
+                let start = data.len();
+                data.extend_from_slice(line.data);
+                // The previous line is only usable if it belongs to the same interlacing pass
+                let prev_start = prev_line
+                    .filter(|&(_, pass)| pass == line.pass)
+                    .map(|(start, _)| start);
+                for i in (0..line.data.len()).step_by(bpp) {
+                    if !is_transparent(&line.data[i..i + bpp]) {
+                        continue;
+                    }
+                    let src = match (fill, i, prev_start) {
+                        (AlphaFill::Up, _, Some(prev)) => Some(prev + i),
+                        (AlphaFill::Left, 0, _) | (AlphaFill::Up, _, None) => {
+                            // Borrow the color of the next opaque pixel in the line
+                            line.data[i..]
+                                .chunks_exact(bpp)
+                                .position(|px| !is_transparent(px))
+                                .map(|p| start + i + p * bpp)
+                        }
+                        _ => Some(start + i - bpp),
+                    };
+                    if let Some(src) = src {
+                        data.copy_within(src..src + colored_bytes, start + i);
+                    }
+                }
+                prev_line = Some((start, line.pass));
+            }
+            PngImage {
+                data,
+                ihdr: png.ihdr.clone(),
+            }
+        }
+        AlphaFill::Nearest => {
+            // Work on the progressive image so that the nearest pixel is spatially correct
+            if png.ihdr.interlaced == Interlacing::Adam7 {
+                let filled = filled_alpha_channel(&deinterlace_image(png), fill)?;
+                return Some(interlace_image(&filled));
+            }
+            let mut data = png.data.clone();
+            let width = png.ihdr.width as usize;
+            let num_pixels = data.len() / bpp;
+            // Breadth-first search from all opaque pixels, propagating their colors outwards
+            let mut visited = vec![false; num_pixels];
+            let mut queue = VecDeque::new();
+            for (i, pixel) in png.data.chunks_exact(bpp).enumerate() {
+                if !is_transparent(pixel) {
+                    visited[i] = true;
+                    queue.push_back(i);
+                }
+            }
+            if queue.is_empty() {
+                return None;
+            }
+            while let Some(i) = queue.pop_front() {
+                let (x, y) = (i % width, i / width);
+                let neighbors = [
+                    (x > 0).then(|| i - 1),
+                    (x + 1 < width).then(|| i + 1),
+                    (y > 0).then(|| i - width),
+                    (i + width < num_pixels).then(|| i + width),
+                ];
+                for n in neighbors.into_iter().flatten() {
+                    if !visited[n] {
+                        visited[n] = true;
+                        data.copy_within(i * bpp..i * bpp + colored_bytes, n * bpp);
+                        queue.push_back(n);
+                    }
+                }
+            }
+            PngImage {
+                data,
+                ihdr: png.ihdr.clone(),
+            }
+        }
+    };
+
+    if filled.data == png.data {
+        return None;
+    }
+    Some(filled)
+}
+
 #[must_use]
 pub fn reduced_alpha_channel(png: &PngImage, optimize_alpha: bool) -> Option<PngImage> {
     if !png.ihdr.color_type.has_alpha() {
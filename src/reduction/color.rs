@@ -1,7 +1,11 @@
-use std::hash::{BuildHasherDefault, Hash};
+use std::{
+    borrow::Cow,
+    hash::{BuildHasherDefault, Hash},
+};
 
 use indexmap::IndexSet;
-use rgb::{alt::Gray, ComponentMap, ComponentSlice, FromSlice, RGB, RGBA};
+use log::trace;
+use rgb::{alt::Gray, ComponentSlice, FromSlice, RGB, RGBA};
 use rustc_hash::FxHasher;
 
 use crate::{
@@ -27,6 +31,7 @@ where
     for pixel in iter {
         let (idx, _) = palette.insert_full(pixel);
         if idx == 256 {
+            trace!("Cannot reduce to indexed: image has more than 256 colors");
             return None;
         }
         reduced.push(idx as u8);
@@ -34,11 +39,12 @@ where
     Some(palette)
 }
 
+/// Attempt to convert to indexed, returning the resulting image if successful
+///
+/// 16-bit images are accepted if every sample is of the form `v * 257`, i.e. both bytes are equal,
+/// as these can be represented exactly in the 8-bit palette.
 #[must_use]
 pub fn reduced_to_indexed(png: &PngImage, allow_grayscale: bool) -> Option<PngImage> {
-    if png.ihdr.bit_depth != BitDepth::Eight {
-        return None;
-    }
     if matches!(png.ihdr.color_type, ColorType::Indexed { .. }) {
         return None;
     }
@@ -46,12 +52,28 @@ pub fn reduced_to_indexed(png: &PngImage, allow_grayscale: bool) -> Option<PngIm
         return None;
     }
 
-    let mut raw_data = Vec::with_capacity(png.data.len() / png.channels_per_pixel());
+    // Reduce 16-bit samples to 8-bit, if this can be done losslessly
+    let data = match png.ihdr.bit_depth {
+        BitDepth::Eight => Cow::Borrowed(&png.data),
+        BitDepth::Sixteen => {
+            if png.data.chunks_exact(2).any(|pair| pair[0] != pair[1]) {
+                trace!("Cannot reduce to indexed: 16-bit samples do not fit losslessly in 8 bits");
+                return None;
+            }
+            Cow::Owned(png.data.iter().step_by(2).copied().collect())
+        }
+        _ => return None,
+    };
+    // Convert the 16-bit transparency to 8-bit
+    // If a 16-bit value doesn't fit losslessly then no pixel can match it, so it is dropped
+    let sixteen = png.ihdr.bit_depth == BitDepth::Sixteen;
+    let trns_to_8 = |t: u16| (!sixteen || t >> 8 == t & 0xFF).then_some(t as u8);
+
+    let mut raw_data = Vec::with_capacity(data.len() / png.channels_per_pixel());
     let palette: Vec<_> = match png.ihdr.color_type {
         ColorType::Grayscale { transparent_shade } => {
-            let pmap = build_palette(png.data.as_gray().iter().copied(), &mut raw_data)?;
-            // Convert the Gray16 transparency to Gray8
-            let transparency_pixel = transparent_shade.map(|t| Gray::from(t as u8));
+            let pmap = build_palette(data.as_gray().iter().copied(), &mut raw_data)?;
+            let transparency_pixel = transparent_shade.and_then(trns_to_8).map(Gray::from);
             pmap.into_iter()
                 .map(|px| {
                     RGB::from(px).with_alpha(if Some(px) != transparency_pixel {
@@ -63,9 +85,9 @@ pub fn reduced_to_indexed(png: &PngImage, allow_grayscale: bool) -> Option<PngIm
                 .collect()
         }
         ColorType::RGB { transparent_color } => {
-            let pmap = build_palette(png.data.as_rgb().iter().copied(), &mut raw_data)?;
-            // Convert the RGB16 transparency to RGB8
-            let transparency_pixel = transparent_color.map(|t| t.map(|c| c as u8));
+            let pmap = build_palette(data.as_rgb().iter().copied(), &mut raw_data)?;
+            let transparency_pixel = transparent_color
+                .and_then(|t| Some(RGB::new(trns_to_8(t.r)?, trns_to_8(t.g)?, trns_to_8(t.b)?)));
             pmap.into_iter()
                 .map(|px| {
                     px.with_alpha(if Some(px) != transparency_pixel {
@@ -77,11 +99,11 @@ pub fn reduced_to_indexed(png: &PngImage, allow_grayscale: bool) -> Option<PngIm
                 .collect()
         }
         ColorType::GrayscaleAlpha => {
-            let pmap = build_palette(png.data.as_gray_alpha().iter().copied(), &mut raw_data)?;
+            let pmap = build_palette(data.as_gray_alpha().iter().copied(), &mut raw_data)?;
             pmap.into_iter().map(RGBA::from).collect()
         }
         ColorType::RGBA => {
-            let pmap = build_palette(png.data.as_rgba().iter().copied(), &mut raw_data)?;
+            let pmap = build_palette(data.as_rgba().iter().copied(), &mut raw_data)?;
             pmap.into_iter().collect()
         }
         _ => return None,
@@ -91,6 +113,7 @@ pub fn reduced_to_indexed(png: &PngImage, allow_grayscale: bool) -> Option<PngIm
         data: raw_data,
         ihdr: IhdrData {
             color_type: ColorType::Indexed { palette },
+            bit_depth: BitDepth::Eight,
             ..png.ihdr
         },
     })
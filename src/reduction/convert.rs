@@ -0,0 +1,273 @@
+use rgb::{RGBA, RGBA16, RGBA8};
+use rustc_hash::FxHashMap;
+
+use crate::{
+    colors::{BitDepth, ColorType},
+    headers::IhdrData,
+    interlace::Interlacing,
+    png::PngImage,
+};
+
+/// Convert an image to the given color type and bit depth, returning the converted image if every
+/// pixel can be represented exactly
+///
+/// This allows a reduction found for one image to be applied to others with the same format, such
+/// as the frames of an APNG. If `optimize_alpha` is set, fully transparent pixels may change color.
+/// If `scale_16` is set, 16-bit samples will be scaled to fit a lower bit depth.
+#[must_use]
+pub fn converted_to_format(
+    png: &PngImage,
+    color_type: &ColorType,
+    bit_depth: BitDepth,
+    optimize_alpha: bool,
+    scale_16: bool,
+) -> Option<PngImage> {
+    // Work with progressive data, the interlacing will be restored at the end
+    let deinterlaced = png.change_interlacing(Interlacing::None);
+    let source = deinterlaced.as_ref().unwrap_or(png);
+    let scale = scale_16 && png.ihdr.bit_depth == BitDepth::Sixteen;
+    let depth = bit_depth as u32;
+    let max = (1 << depth) - 1;
+    let to_depth = |v: u16| sample_to_depth(v, depth, scale);
+
+    let mut packer = Packer::new(depth);
+    let transparent_index = match color_type {
+        ColorType::Indexed { palette } => palette.iter().position(|c| c.a == 0),
+        _ => None,
+    };
+    let mut palette_map = FxHashMap::default();
+    if let ColorType::Indexed { palette } = color_type {
+        for (i, &color) in palette.iter().enumerate().rev() {
+            palette_map.insert(color, i as u16);
+        }
+    }
+
+    for row in source_pixels(source)? {
+        for px in row {
+            let transparent = px.a == 0;
+            match color_type {
+                ColorType::Grayscale { transparent_shade } => {
+                    let gray = (px.r == px.g && px.g == px.b)
+                        .then(|| to_depth(px.r))
+                        .flatten();
+                    let value = match (transparent, *transparent_shade) {
+                        (true, Some(t)) if optimize_alpha || gray == Some(t) => t,
+                        (false, t) if px.a == u16::MAX && gray.is_some() && gray != t => gray?,
+                        _ => return None,
+                    };
+                    packer.push(value);
+                }
+                ColorType::RGB { transparent_color } => {
+                    let color = to_depth(px.r)
+                        .zip(to_depth(px.g))
+                        .zip(to_depth(px.b))
+                        .map(|((r, g), b)| [r, g, b]);
+                    let trns = transparent_color.map(|t| [t.r, t.g, t.b]);
+                    let values = match (transparent, trns) {
+                        (true, Some(t)) if optimize_alpha || color == Some(t) => t,
+                        (false, t) if px.a == u16::MAX && color.is_some() && color != t => color?,
+                        _ => return None,
+                    };
+                    values.into_iter().for_each(|v| packer.push(v));
+                }
+                ColorType::GrayscaleAlpha if transparent && optimize_alpha => {
+                    packer.push(0);
+                    packer.push(0);
+                }
+                ColorType::GrayscaleAlpha => {
+                    if px.r != px.g || px.g != px.b {
+                        return None;
+                    }
+                    packer.push(to_depth(px.r)?);
+                    packer.push(to_depth(px.a)?);
+                }
+                ColorType::RGBA if transparent && optimize_alpha => {
+                    (0..4).for_each(|_| packer.push(0));
+                }
+                ColorType::RGBA => {
+                    for v in [px.r, px.g, px.b, px.a] {
+                        packer.push(to_depth(v)?);
+                    }
+                }
+                ColorType::Indexed { .. } => {
+                    let to_8 = |v: u16| sample_to_depth(v, 8, scale).map(|v| v as u8);
+                    let color = RGBA8::new(to_8(px.r)?, to_8(px.g)?, to_8(px.b)?, to_8(px.a)?);
+                    let index = match palette_map.get(&color) {
+                        Some(&i) => i,
+                        None if transparent && optimize_alpha => transparent_index? as u16,
+                        None => return None,
+                    };
+                    if index > max {
+                        return None;
+                    }
+                    packer.push(index);
+                }
+            }
+        }
+        packer.end_row();
+    }
+
+    let converted = PngImage {
+        data: packer.data,
+        ihdr: IhdrData {
+            color_type: color_type.clone(),
+            bit_depth,
+            interlaced: Interlacing::None,
+            ..png.ihdr
+        },
+    };
+    Some(
+        converted
+            .change_interlacing(png.ihdr.interlaced)
+            .unwrap_or(converted),
+    )
+}
+
+/// Read the pixels of an image as 16-bit RGBA, in progressive order
+#[must_use]
+pub fn rgba16_pixels(png: &PngImage) -> Option<Vec<RGBA16>> {
+    let deinterlaced = png.change_interlacing(Interlacing::None);
+    let rows = source_pixels(deinterlaced.as_ref().unwrap_or(png))?;
+    Some(rows.into_iter().flatten().collect())
+}
+
+/// Create an image of the given format from 16-bit RGBA pixels, if every pixel can be
+/// represented exactly
+#[must_use]
+pub fn image_from_rgba16(
+    pixels: &[RGBA16],
+    width: u32,
+    height: u32,
+    format: &IhdrData,
+) -> Option<PngImage> {
+    let rgba = PngImage {
+        data: pixels
+            .iter()
+            .flat_map(|px| [px.r, px.g, px.b, px.a])
+            .flat_map(u16::to_be_bytes)
+            .collect(),
+        ihdr: IhdrData {
+            width,
+            height,
+            color_type: ColorType::RGBA,
+            bit_depth: BitDepth::Sixteen,
+            interlaced: Interlacing::None,
+        },
+    };
+    let image = converted_to_format(&rgba, &format.color_type, format.bit_depth, false, false)?;
+    Some(image.change_interlacing(format.interlaced).unwrap_or(image))
+}
+
+/// Read the pixels of a progressive image as rows of 16-bit RGBA
+fn source_pixels(png: &PngImage) -> Option<Vec<Vec<RGBA16>>> {
+    let depth = png.ihdr.bit_depth as u32;
+    let channels = png.channels_per_pixel();
+    // Scale samples to 16 bits, which is exact for all bit depths
+    let factor = u32::from(u16::MAX) / ((1 << depth) - 1);
+    let mut rows = Vec::with_capacity(png.ihdr.height as usize);
+    for line in png.scan_lines(false) {
+        let sample = |i: usize| -> u16 {
+            match depth {
+                16 => u16::from_be_bytes([line.data[i * 2], line.data[i * 2 + 1]]),
+                8 => u16::from(line.data[i]),
+                _ => {
+                    let bit = i * depth as usize;
+                    let shift = 8 - depth as usize - bit % 8;
+                    u16::from((line.data[bit / 8] >> shift) & ((1 << depth) - 1))
+                }
+            }
+        };
+        let scaled = |i: usize| (u32::from(sample(i)) * factor) as u16;
+        let mut row = Vec::with_capacity(line.num_pixels);
+        for p in 0..line.num_pixels {
+            let i = p * channels;
+            let px = match &png.ihdr.color_type {
+                ColorType::Grayscale { transparent_shade } => {
+                    let a = if Some(sample(i)) == *transparent_shade {
+                        0
+                    } else {
+                        u16::MAX
+                    };
+                    RGBA::new(scaled(i), scaled(i), scaled(i), a)
+                }
+                ColorType::RGB { transparent_color } => {
+                    let raw = [sample(i), sample(i + 1), sample(i + 2)];
+                    let a = if transparent_color.map(|t| [t.r, t.g, t.b]) == Some(raw) {
+                        0
+                    } else {
+                        u16::MAX
+                    };
+                    RGBA::new(scaled(i), scaled(i + 1), scaled(i + 2), a)
+                }
+                ColorType::Indexed { palette } => {
+                    let c = palette.get(sample(i) as usize)?;
+                    let wide = |v: u8| u16::from(v) * 257;
+                    RGBA::new(wide(c.r), wide(c.g), wide(c.b), wide(c.a))
+                }
+                ColorType::GrayscaleAlpha => {
+                    RGBA::new(scaled(i), scaled(i), scaled(i), scaled(i + 1))
+                }
+                ColorType::RGBA => {
+                    RGBA::new(scaled(i), scaled(i + 1), scaled(i + 2), scaled(i + 3))
+                }
+            };
+            row.push(px);
+        }
+        rows.push(row);
+    }
+    Some(rows)
+}
+
+/// Convert a 16-bit sample to the given bit depth, if it can be represented exactly
+fn sample_to_depth(v: u16, depth: u32, scale: bool) -> Option<u16> {
+    if depth == 16 {
+        return Some(v);
+    }
+    let v8 = if v % 257 == 0 {
+        v / 257
+    } else if scale {
+        // Same rounding as the scaled bit depth reduction
+        (f32::from(v) * (255.0 / 65535.0)).round() as u16
+    } else {
+        return None;
+    };
+    let factor = 255 / ((1 << depth) - 1);
+    (v8 % factor == 0).then_some(v8 / factor)
+}
+
+/// Packs samples of a given bit depth into rows of bytes
+struct Packer {
+    data: Vec<u8>,
+    depth: u32,
+    bits: u32,
+}
+
+impl Packer {
+    fn new(depth: u32) -> Self {
+        Self {
+            data: Vec::new(),
+            depth,
+            bits: 0,
+        }
+    }
+
+    fn push(&mut self, value: u16) {
+        match self.depth {
+            16 => self.data.extend_from_slice(&value.to_be_bytes()),
+            8 => self.data.push(value as u8),
+            depth => {
+                if self.bits == 0 {
+                    self.data.push(0);
+                }
+                self.bits += depth;
+                *self.data.last_mut().unwrap() |= (value as u8) << (8 - self.bits);
+                self.bits %= 8;
+            }
+        }
+    }
+
+    /// Pad the current row to a whole byte
+    fn end_row(&mut self) {
+        self.bits = 0;
+    }
+}
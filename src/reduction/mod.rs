@@ -1,6 +1,8 @@
 use std::sync::Arc;
 
-use crate::{evaluate::Evaluator, png::PngImage, ColorType, Deadline, Deflaters, Options};
+use crate::{
+    evaluate::Evaluator, png::PngImage, ColorType, Deadline, Deflaters, InterlaceMode, Options,
+};
 
 pub mod alpha;
 use crate::alpha::*;
@@ -8,6 +10,7 @@ pub mod bit_depth;
 use crate::bit_depth::*;
 pub mod color;
 use crate::color::*;
+pub mod convert;
 pub mod palette;
 use crate::palette::*;
 
@@ -22,12 +25,15 @@ pub(crate) fn perform_reductions(
     // At low compression levels, skip some transformations which are less likely to be effective
     // This currently affects optimization presets 0-2
     let cheap = match opts.deflate {
-        Deflaters::Libdeflater { compression } => compression < 12 && opts.fast_evaluation,
+        Deflaters::Libdeflater { compression } => {
+            compression < 12 && opts.fast_evaluation && !opts.exhaustive
+        }
         _ => false,
     };
 
     // Interlacing must be processed first in order to evaluate the rest correctly
-    if let Some(interlacing) = opts.interlace {
+    // In auto mode, the existing interlacing is kept here and the alternative is evaluated later
+    if let InterlaceMode::Set(interlacing) = opts.interlace {
         if let Some(reduced) = png.change_interlacing(interlacing) {
             png = Arc::new(reduced);
         }
@@ -94,7 +100,10 @@ pub(crate) fn perform_reductions(
             png = Arc::new(reduced);
             // For small differences, if a tRNS chunk is required then enter this into the evaluator
             // Otherwise it is mostly just removal of bytes and should become the baseline
-            if png.ihdr.color_type.has_trns() && baseline.data.len() - png.data.len() <= 1000 {
+            // In exhaustive mode, we always enter it into the evaluator
+            if opts.exhaustive
+                || png.ihdr.color_type.has_trns() && baseline.data.len() - png.data.len() <= 1000
+            {
                 eval.try_image(png.clone());
                 evaluation_added = true;
             } else {
@@ -116,6 +125,7 @@ pub(crate) fn perform_reductions(
     }
 
     // Attempt to reduce to indexed
+    // 16-bit images can be reduced here even if bit depth reduction is disabled, provided it is lossless
     // Keep the existing `png` var in case it is grayscale - we can test both for depth reduction later
     let mut indexed = None;
     if opts.color_type_reduction && !deadline.passed() {
@@ -123,8 +133,8 @@ pub(crate) fn perform_reductions(
             // Make sure the palette gets sorted (but don't bother evaluating both results)
             let new = Arc::new(sorted_palette(&reduced).unwrap_or(reduced));
             // For relatively small differences, enter this into the evaluator
-            // Otherwise we're confident enough for it to become the baseline
-            if png.data.len() - new.data.len() <= INDEXED_MAX_DIFF {
+            // Otherwise we're confident enough for it to become the baseline (unless in exhaustive mode)
+            if opts.exhaustive || png.data.len() - new.data.len() <= INDEXED_MAX_DIFF {
                 eval.try_image_with_description(new.clone(), "Indexed (luma sort)");
                 evaluation_added = true;
             } else {
@@ -145,33 +155,46 @@ pub(crate) fn perform_reductions(
         // This one doesn't need to be kept in the palette list as the sorters will fail if there's no change
         let input = indexed.as_ref().unwrap_or(&png);
 
-        // Attempt to sort the palette using the battiato method
-        if !deadline.passed() {
-            if let Some(reduced) = sorted_palette_battiato(input) {
-                if let ColorType::Indexed { palette } = &reduced.ihdr.color_type {
-                    if !palettes.contains(palette) {
-                        palettes.push(palette.clone());
-                        eval.try_image_with_description(
-                            Arc::new(reduced),
-                            "Indexed (battiato sort)",
-                        );
-                        evaluation_added = true;
-                    }
+        // Enter a sorted image into the evaluator if its palette hasn't been seen already
+        let mut try_sorted = |reduced: Option<PngImage>, description: &str| {
+            let Some(reduced) = reduced else {
+                return;
+            };
+            if let ColorType::Indexed { palette } = &reduced.ihdr.color_type {
+                if !palettes.contains(palette) {
+                    palettes.push(palette.clone());
+                    eval.try_image_with_description(Arc::new(reduced), description);
+                    evaluation_added = true;
                 }
             }
+        };
+
+        // Attempt to sort the palette using the battiato method
+        if !deadline.passed() {
+            try_sorted(sorted_palette_battiato(input), "Indexed (battiato sort)");
         }
 
         // Attempt to sort the palette using the mzeng method
         if !deadline.passed() {
-            if let Some(reduced) = sorted_palette_mzeng(input) {
-                if let ColorType::Indexed { palette } = &reduced.ihdr.color_type {
-                    if !palettes.contains(palette) {
-                        palettes.push(palette.clone());
-                        eval.try_image_with_description(Arc::new(reduced), "Indexed (mzeng sort)");
-                        evaluation_added = true;
-                    }
-                }
-            }
+            try_sorted(sorted_palette_mzeng(input), "Indexed (mzeng sort)");
+        }
+
+        // Attempt to sort the palette using a nearest-neighbor tour
+        if !deadline.passed() {
+            try_sorted(
+                sorted_palette_nearest_neighbor(input),
+                "Indexed (nearest sort)",
+            );
+        }
+
+        // Attempt to sort the palette by frequency
+        if !deadline.passed() {
+            try_sorted(sorted_palette_frequency(input), "Indexed (frequency sort)");
+        }
+
+        // Attempt to refine the mzeng sort with simulated annealing
+        if opts.palette_annealing && !deadline.passed() {
+            try_sorted(sorted_palette_annealing(input), "Indexed (anneal sort)");
         }
     }
 
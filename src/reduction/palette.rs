@@ -1,3 +1,5 @@
+use std::{cmp::Reverse, collections::VecDeque};
+
 use indexmap::IndexSet;
 use rgb::RGBA8;
 
@@ -5,6 +7,7 @@ use crate::{
     colors::{BitDepth, ColorType},
     headers::IhdrData,
     png::{scan_lines::ScanLine, PngImage},
+    rng::XorShift,
     Interlacing,
 };
 
@@ -174,6 +177,76 @@ pub fn sorted_palette_battiato(png: &PngImage) -> Option<PngImage> {
     apply_palette_reorder(png, &remapping)
 }
 
+/// Sort the colors in the palette using a nearest-neighbor tour of the co-occurrence graph,
+/// returning the sorted image if successful
+#[must_use]
+pub fn sorted_palette_nearest_neighbor(png: &PngImage) -> Option<PngImage> {
+    // Interlacing not currently supported
+    if png.ihdr.bit_depth != BitDepth::Eight || png.ihdr.interlaced != Interlacing::None {
+        return None;
+    }
+    let palette = match &png.ihdr.color_type {
+        // Images with only two colors will remain unchanged from previous luma sort
+        ColorType::Indexed { palette } if palette.len() > 2 => palette,
+        _ => return None,
+    };
+
+    let matrix = co_occurrence_matrix(palette.len(), png);
+    let edges = weighted_edges(&matrix);
+    let mut remapping = nearest_neighbor_reindex(palette.len(), edges[0], &matrix);
+
+    apply_most_popular_color(png, &mut remapping);
+
+    apply_palette_reorder(png, &remapping)
+}
+
+/// Sort the colors in the palette using the mzeng technique followed by a simulated annealing
+/// refinement, returning the sorted image if successful
+#[must_use]
+pub fn sorted_palette_annealing(png: &PngImage) -> Option<PngImage> {
+    // Interlacing not currently supported
+    if png.ihdr.bit_depth != BitDepth::Eight || png.ihdr.interlaced != Interlacing::None {
+        return None;
+    }
+    let palette = match &png.ihdr.color_type {
+        // Images with only two colors will remain unchanged from previous luma sort
+        ColorType::Indexed { palette } if palette.len() > 2 => palette,
+        _ => return None,
+    };
+
+    let matrix = co_occurrence_matrix(palette.len(), png);
+    let edges = weighted_edges(&matrix);
+    let remapping = mzeng_reindex(palette.len(), edges, &matrix);
+    let mut remapping = anneal_reindex(remapping, &matrix);
+
+    apply_most_popular_color(png, &mut remapping);
+
+    apply_palette_reorder(png, &remapping)
+}
+
+/// Sort the colors in the palette by descending frequency, returning the sorted image if successful
+#[must_use]
+pub fn sorted_palette_frequency(png: &PngImage) -> Option<PngImage> {
+    if png.ihdr.bit_depth != BitDepth::Eight {
+        return None;
+    }
+    let palette = match &png.ihdr.color_type {
+        // Images with only two colors will remain unchanged from previous luma sort
+        ColorType::Indexed { palette } if palette.len() > 2 => palette,
+        _ => return None,
+    };
+
+    let mut counts = [0u32; 256];
+    for &val in &png.data {
+        counts[val as usize] += 1;
+    }
+    // A stable sort ensures colors with equal counts keep their current order
+    let mut remapping: Vec<_> = (0..palette.len()).collect();
+    remapping.sort_by_key(|&i| Reverse(counts[i]));
+
+    apply_palette_reorder(png, &remapping)
+}
+
 // Apply the palette reordering to the image data
 fn apply_palette_reorder(png: &PngImage, remapping: &[usize]) -> Option<PngImage> {
     let ColorType::Indexed { palette } = &png.ihdr.color_type else {
@@ -318,7 +391,8 @@ fn mzeng_reindex(num_colors: usize, edges: Vec<(usize, usize)>, matrix: &[Vec<u3
             continue;
         }
         let sum = (i, m_row[remapping[0]] + m_row[remapping[1]]);
-        if sum.1 > best_sum.1 {
+        // Colors with no connection to the remapping must still be picked eventually
+        if sums.is_empty() || sum.1 > best_sum.1 {
             best_sum_pos = sums.len();
             best_sum = sum;
         }
@@ -346,7 +420,7 @@ fn mzeng_reindex(num_colors: usize, edges: Vec<(usize, usize)>, matrix: &[Vec<u3
             best_sum = (0, 0);
             for (i, sum) in sums.iter_mut().enumerate() {
                 sum.1 += matrix[best_index][sum.0];
-                if sum.1 > best_sum.1 {
+                if i == 0 || sum.1 > best_sum.1 {
                     best_sum_pos = i;
                     best_sum = *sum;
                 }
@@ -438,3 +512,142 @@ fn battiato_reindex(num_colors: usize, edges: Vec<(usize, usize)>) -> Vec<usize>
     // Return the completed chain
     chains.swap_remove(0)
 }
+
+// Build a path through the co-occurrence graph, starting from the heaviest edge and repeatedly
+// extending whichever end has the strongest connection to an unvisited color
+fn nearest_neighbor_reindex(
+    num_colors: usize,
+    first_edge: (usize, usize),
+    matrix: &[Vec<u32>],
+) -> Vec<usize> {
+    let mut remapping = VecDeque::with_capacity(num_colors);
+    remapping.push_back(first_edge.0);
+    remapping.push_back(first_edge.1);
+    let mut visited = vec![false; num_colors];
+    visited[first_edge.0] = true;
+    visited[first_edge.1] = true;
+
+    while remapping.len() < num_colors {
+        let head = remapping[0];
+        let tail = remapping[remapping.len() - 1];
+        // Ties are broken by the lowest index, keeping the result deterministic
+        let mut best = (0, usize::MAX, false);
+        for (i, _) in visited.iter().enumerate().filter(|(_, &v)| !v) {
+            let (weight, at_head) = if matrix[head][i] > matrix[tail][i] {
+                (matrix[head][i], true)
+            } else {
+                (matrix[tail][i], false)
+            };
+            if best.1 == usize::MAX || weight > best.0 {
+                best = (weight, i, at_head);
+            }
+        }
+        visited[best.1] = true;
+        if best.2 {
+            remapping.push_front(best.1);
+        } else {
+            remapping.push_back(best.1);
+        }
+    }
+
+    remapping.into()
+}
+
+/// Number of candidate swaps to try when refining a palette order with simulated annealing
+const ANNEAL_ITERATIONS: usize = 10000;
+/// Number of initial swaps used to calibrate the annealing temperature
+const ANNEAL_CALIBRATION: usize = 100;
+
+// Refine a palette order using simulated annealing, scoring each order by the entropy of the
+// differences between neighboring indices, which approximates the cost after Sub/Up filtering
+fn anneal_reindex(mut remapping: Vec<usize>, matrix: &[Vec<u32>]) -> Vec<usize> {
+    let num_colors = remapping.len();
+    let mut pos = vec![0u8; num_colors];
+    for (i, &c) in remapping.iter().enumerate() {
+        pos[c] = i as u8;
+    }
+
+    // Histogram of index differences, weighted by co-occurrence
+    let mut hist = [0u64; 256];
+    for (i, m_row) in matrix.iter().enumerate() {
+        for (j, &val) in m_row.iter().enumerate() {
+            hist[pos[j].wrapping_sub(pos[i]) as usize] += u64::from(val);
+        }
+    }
+    // Higher is better, as the entropy is N*log2(N) minus this value
+    let score = |hist: &[u64; 256]| -> i64 {
+        hist.iter()
+            .filter(|&&c| c != 0)
+            .map(|&c| ilog2i(c) as i64)
+            .sum()
+    };
+    // Add or remove all contributions to the histogram involving colors `a` and `b`
+    let update = |hist: &mut [u64; 256], pos: &[u8], a: usize, b: usize, add: bool| {
+        let mut apply = |i: usize, j: usize| {
+            let diff = pos[j].wrapping_sub(pos[i]) as usize;
+            let val = u64::from(matrix[i][j]);
+            if add {
+                hist[diff] += val;
+            } else {
+                hist[diff] -= val;
+            }
+        };
+        for k in 0..num_colors {
+            apply(a, k);
+            apply(b, k);
+            if k != a && k != b {
+                apply(k, a);
+                apply(k, b);
+            }
+        }
+    };
+
+    let mut current = score(&hist);
+    let mut best = (current, remapping.clone());
+    // Use a fixed seed so that results are consistent between runs
+    let mut rng = XorShift::new(0x9E37_79B9);
+    let mut temperature = 0.0;
+    for iteration in 0..ANNEAL_CALIBRATION + ANNEAL_ITERATIONS {
+        let p = rng.below(num_colors);
+        let q = rng.below(num_colors);
+        if p == q {
+            continue;
+        }
+        let (a, b) = (remapping[p], remapping[q]);
+        update(&mut hist, &pos, a, b, false);
+        pos.swap(a, b);
+        update(&mut hist, &pos, a, b, true);
+        let new = score(&hist);
+        let delta = (current - new) as f64;
+
+        let accept = if iteration < ANNEAL_CALIBRATION {
+            // Measure the average cost of a swap in order to set the starting temperature
+            temperature += delta.abs() / ANNEAL_CALIBRATION as f64;
+            delta <= 0.0
+        } else {
+            let remaining = (ANNEAL_CALIBRATION + ANNEAL_ITERATIONS - iteration) as f64;
+            let t = temperature * remaining / ANNEAL_ITERATIONS as f64;
+            delta <= 0.0 || (t > 0.0 && rng.next_f64() < (-delta / t).exp())
+        };
+        if accept {
+            remapping.swap(p, q);
+            current = new;
+            if current > best.0 {
+                best = (current, remapping.clone());
+            }
+        } else {
+            // Revert the swap
+            update(&mut hist, &pos, a, b, false);
+            pos.swap(a, b);
+            update(&mut hist, &pos, a, b, true);
+        }
+    }
+
+    best.1
+}
+
+// Integer approximation for i * log2(i), as used by the Entropy filter but with 64-bit range
+const fn ilog2i(i: u64) -> u64 {
+    let log = 63 - i.leading_zeros() as u64;
+    i * log + ((i - (1 << log)) << 1)
+}
@@ -0,0 +1,26 @@
+/// Minimal pseudo-random number generator, for reproducible results
+#[derive(Debug, Clone)]
+pub struct XorShift(u32);
+
+impl XorShift {
+    #[must_use]
+    pub const fn new(seed: u32) -> Self {
+        Self(seed)
+    }
+
+    pub fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    pub fn next_f64(&mut self) -> f64 {
+        f64::from(self.next()) / f64::from(u32::MAX)
+    }
+
+    /// Generate a random number in the range `0..n`
+    pub fn below(&mut self, n: usize) -> usize {
+        self.next() as usize % n
+    }
+}
@@ -3,6 +3,7 @@ use std::io::Cursor;
 use image::{codecs::png::PngDecoder, *};
 use log::{error, warn};
 
+use crate::apng::gcd;
 #[cfg(not(feature = "parallel"))]
 use crate::rayon;
 
@@ -25,30 +26,66 @@ pub fn validate_output(output: &[u8], original_data: &[u8]) -> bool {
             warn!("Failed to read input image for validation: {}", old_err);
             true
         }
-        (Ok(new_frames), Ok(old_frames)) if new_frames.len() != old_frames.len() => false,
         (Ok(new_frames), Ok(old_frames)) => {
-            for (a, b) in old_frames.iter().zip(new_frames) {
+            // Identical consecutive frames may have been merged, so compare what is displayed over time
+            let (old_frames, new_frames) = (timeline(old_frames), timeline(new_frames));
+            if new_frames.len() != old_frames.len() {
+                return false;
+            }
+            let animated = new_frames.len() > 1;
+            for ((a, a_delay), (b, b_delay)) in old_frames.iter().zip(new_frames) {
                 if !images_equal(a, &b) {
                     return false;
                 }
+                if animated
+                    && u128::from(a_delay.0) * u128::from(b_delay.1)
+                        != u128::from(b_delay.0) * u128::from(a_delay.1)
+                {
+                    return false;
+                }
             }
             true
         }
     }
 }
 
-/// Loads a PNG image from memory to frames of [RgbaImage]
-fn load_png_image_from_memory(png_data: &[u8]) -> Result<Vec<RgbaImage>, image::ImageError> {
+/// Frames of an image, each with its delay as a fraction
+type Frames<T> = Vec<(RgbaImage, (T, T))>;
+
+/// Loads a PNG image from memory to frames of [RgbaImage], with their delays in milliseconds
+fn load_png_image_from_memory(png_data: &[u8]) -> Result<Frames<u32>, image::ImageError> {
     let decoder = PngDecoder::new(Cursor::new(png_data))?;
     if decoder.is_apng()? {
         decoder
             .apng()?
             .into_frames()
-            .map(|f| f.map(|f| f.into_buffer()))
+            .map(|f| {
+                f.map(|f| {
+                    let delay = f.delay().numer_denom_ms();
+                    (f.into_buffer(), delay)
+                })
+            })
             .collect()
     } else {
-        DynamicImage::from_decoder(decoder).map(|i| vec![i.into_rgba8()])
+        DynamicImage::from_decoder(decoder).map(|i| vec![(i.into_rgba8(), (0, 1))])
+    }
+}
+
+/// Merge identical consecutive frames, summing their delays as fractions
+fn timeline(frames: Frames<u32>) -> Frames<u64> {
+    let mut merged: Frames<u64> = Vec::with_capacity(frames.len());
+    for (image, (num, den)) in frames {
+        let (num, den) = (u64::from(num), u64::from(den.max(1)));
+        match merged.last_mut() {
+            Some((last, (last_num, last_den))) if images_equal(last, &image) => {
+                let sum = (*last_num * den + num * *last_den, *last_den * den);
+                let divisor = gcd(sum.0, sum.1);
+                (*last_num, *last_den) = (sum.0 / divisor, sum.1 / divisor);
+            }
+            _ => merged.push((image, (num, den))),
+        }
     }
+    merged
 }
 
 /// Compares images pixel by pixel for equivalent content
@@ -0,0 +1,207 @@
+//! Strategies for choosing the delta filter of each line of an image
+
+use std::fmt;
+
+use bitvec::bitarr;
+use libdeflater::{CompressionLvl, Compressor};
+use rustc_hash::FxHashMap;
+
+use crate::filters::RowFilter;
+
+/// Compression level to use for the Brute filter strategy
+const BRUTE_LEVEL: i32 = 1; // 1 is fastest, 2-4 are not useful, 5 is slower but more effective
+/// Number of lines to compress with the Brute filter strategy
+const BRUTE_LINES: usize = 4; // Values over 8 are generally not useful
+
+/// Strategy for choosing the delta filter of each line of an image
+///
+/// For each line, each of the applicable standard filters is applied and the strategy chooses
+/// one of the results. The heuristic [`RowFilter`]s are built-in implementations of this trait,
+/// and custom strategies may be evaluated alongside them using
+/// [`Options::filter_strategies`][crate::Options::filter_strategies].
+pub trait FilterStrategy: fmt::Debug + Send + Sync {
+    /// Name of the strategy, for display in verbose output
+    fn name(&self) -> &str {
+        "Custom"
+    }
+
+    /// Choose one of the candidate lines, returning its index
+    ///
+    /// Each candidate is a filtered line, beginning with its filter type byte. The first line of
+    /// each interlacing pass is only given the `None` and `Sub` filters, otherwise all five
+    /// standard filters are given in order.
+    fn choose(&self, candidates: &[&[u8]], context: &LineContext<'_>) -> usize;
+}
+
+/// Context for a line being filtered by a [`FilterStrategy`]
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct LineContext<'a> {
+    /// Index of the line, counting from the start of the image across all interlacing passes
+    pub index: usize,
+    /// Interlacing pass of the line, if the image is interlaced
+    pub pass: Option<u8>,
+    /// Number of bytes per pixel, rounded up to 1
+    pub bpp: usize,
+    /// Unfiltered data of the previous line in the same pass, or zeros for the first line
+    pub prev_line: &'a [u8],
+    /// Filtered data of all previous lines
+    pub filtered: &'a [u8],
+}
+
+impl RowFilter {
+    /// Get the implementation of a heuristic strategy, or `None` for other filter types
+    #[must_use]
+    pub fn strategy(self) -> Option<&'static dyn FilterStrategy> {
+        match self {
+            Self::MinSum => Some(&MinSum),
+            Self::Entropy => Some(&Entropy),
+            Self::Bigrams => Some(&Bigrams),
+            Self::BigEnt => Some(&BigEnt),
+            Self::Brute => Some(&Brute),
+            _ => None,
+        }
+    }
+}
+
+/// Return the index of the first candidate with the lowest score
+fn min_index<T: PartialOrd>(candidates: &[&[u8]], mut score: impl FnMut(&[u8]) -> T) -> usize {
+    let mut best: Option<(usize, T)> = None;
+    for (i, line) in candidates.iter().enumerate() {
+        let size = score(line);
+        if best.as_ref().map_or(true, |(_, b)| size < *b) {
+            best = Some((i, size));
+        }
+    }
+    best.map_or(0, |(i, _)| i)
+}
+
+/// Minimum sum of absolute differences
+#[derive(Debug)]
+struct MinSum;
+
+impl FilterStrategy for MinSum {
+    fn name(&self) -> &str {
+        "MinSum"
+    }
+
+    fn choose(&self, candidates: &[&[u8]], _: &LineContext<'_>) -> usize {
+        // MSAD algorithm mentioned in libpng reference docs
+        // http://www.libpng.org/pub/png/book/chapter09.html
+        min_index(candidates, |line| {
+            line.iter()
+                .fold(0, |acc, &x| acc + (x as i8).unsigned_abs() as usize)
+        })
+    }
+}
+
+/// Highest Shannon entropy
+#[derive(Debug)]
+struct Entropy;
+
+impl FilterStrategy for Entropy {
+    fn name(&self) -> &str {
+        "Entropy"
+    }
+
+    fn choose(&self, candidates: &[&[u8]], _: &LineContext<'_>) -> usize {
+        // Shannon entropy algorithm, from LodePNG
+        // https://github.com/lvandeve/lodepng
+        min_index(candidates, |line| {
+            let mut counts = [0; 0x100];
+            for &i in line {
+                counts[i as usize] += 1;
+            }
+            let size = counts.into_iter().fold(0, |acc, x| {
+                if x == 0 {
+                    return acc;
+                }
+                acc + ilog2i(x)
+            }) as i32;
+            -size
+        })
+    }
+}
+
+/// Lowest count of distinct bigrams
+#[derive(Debug)]
+struct Bigrams;
+
+impl FilterStrategy for Bigrams {
+    fn name(&self) -> &str {
+        "Bigrams"
+    }
+
+    fn choose(&self, candidates: &[&[u8]], _: &LineContext<'_>) -> usize {
+        // Count distinct bigrams, from pngwolf
+        // https://bjoern.hoehrmann.de/pngwolf/
+        let mut set = bitarr![0; 0x10000];
+        min_index(candidates, |line| {
+            set.fill(false);
+            for pair in line.windows(2) {
+                let bigram = ((pair[0] as usize) << 8) | pair[1] as usize;
+                set.set(bigram, true);
+            }
+            set.count_ones()
+        })
+    }
+}
+
+/// Highest Shannon entropy of bigrams
+#[derive(Debug)]
+struct BigEnt;
+
+impl FilterStrategy for BigEnt {
+    fn name(&self) -> &str {
+        "BigEnt"
+    }
+
+    fn choose(&self, candidates: &[&[u8]], _: &LineContext<'_>) -> usize {
+        // Bigram entropy, combined from Entropy and Bigrams filters
+        // FxHasher is the fastest rust hasher currently available for this purpose
+        let mut counts = FxHashMap::<u16, u32>::default();
+        min_index(candidates, |line| {
+            counts.clear();
+            for pair in line.windows(2) {
+                let bigram = (u16::from(pair[0]) << 8) | u16::from(pair[1]);
+                counts.entry(bigram).and_modify(|e| *e += 1).or_insert(1);
+            }
+            -(counts.values().fold(0, |acc, &x| acc + ilog2i(x)) as i32)
+        })
+    }
+}
+
+/// Smallest compressed size
+#[derive(Debug)]
+struct Brute;
+
+impl FilterStrategy for Brute {
+    fn name(&self) -> &str {
+        "Brute"
+    }
+
+    fn choose(&self, candidates: &[&[u8]], context: &LineContext<'_>) -> usize {
+        // Brute force by compressing each filter attempt
+        // Similar to that of LodePNG but includes some previous lines for context
+        let line_len = candidates.first().map_or(0, |line| line.len());
+        let filtered = context.filtered;
+        let limit = (filtered.len() + line_len).min(line_len * BRUTE_LINES);
+        let mut data = filtered[filtered.len() + line_len - limit..].to_vec();
+        let line_start = data.len();
+        data.resize(limit, 0);
+        let mut compressor = Compressor::new(CompressionLvl::new(BRUTE_LEVEL).unwrap());
+        let mut dest = vec![0; compressor.zlib_compress_bound(limit)];
+        min_index(candidates, |line| {
+            data[line_start..].copy_from_slice(line);
+            compressor
+                .zlib_compress(&data, &mut dest)
+                .unwrap_or(usize::MAX)
+        })
+    }
+}
+
+// Integer approximation for i * log2(i) - much faster than float calculations
+const fn ilog2i(i: u32) -> u32 {
+    let log = 32 - i.leading_zeros() - 1;
+    i * log + ((i - (1 << log)) << 1)
+}
@@ -0,0 +1,149 @@
+use indexmap::IndexSet;
+
+/// The keyword of the iTXt chunk that holds XMP data
+pub const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
+/// Strip properties from an XMP packet, returning `None` if it can't be parsed
+///
+/// Properties are given as qualified names such as `xmpMM:History`, or `prefix:*` to strip all
+/// properties of a namespace. Prefixes are matched as written in the packet. Both elements and
+/// attributes are stripped, and everything else is kept as is.
+#[must_use]
+pub fn strip_xmp(xmp: &str, properties: &IndexSet<String>) -> Option<String> {
+    let mut output = String::with_capacity(xmp.len());
+    let mut rest = xmp;
+    while let Some(start) = rest.find('<') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        // Markup other than elements is kept as is
+        let markup_end = [
+            ("<!--", "-->"),
+            ("<![CDATA[", "]]>"),
+            ("<?", "?>"),
+            ("</", ">"),
+        ]
+        .iter()
+        .find(|(open, _)| rest.starts_with(open))
+        .map(|(_, close)| rest.find(close).map(|i| i + close.len()));
+        if let Some(end) = markup_end {
+            let end = end?;
+            output.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+
+        let tag = Tag::parse(rest)?;
+        if is_stripped(tag.name, properties) {
+            // Skip the element and its contents, along with the whitespace that precedes it
+            rest = &rest[tag.len..];
+            if !tag.self_closing {
+                rest = &rest[element_content_len(rest)?..];
+            }
+            output.truncate(output.trim_end().len());
+            continue;
+        }
+        output.push('<');
+        output.push_str(tag.name);
+        for (text, name) in tag.attributes {
+            if !is_stripped(name, properties) {
+                output.push_str(text);
+            }
+        }
+        output.push_str(tag.end);
+        rest = &rest[tag.len..];
+    }
+    output.push_str(rest);
+    Some(output)
+}
+
+/// Whether a property is in the set to strip, either by name or by namespace
+fn is_stripped(name: &str, properties: &IndexSet<String>) -> bool {
+    properties.contains(name)
+        || name
+            .split_once(':')
+            .is_some_and(|(prefix, _)| properties.contains(&format!("{prefix}:*")))
+}
+
+/// Find the length of the content of an element up to and including its end tag
+fn element_content_len(xmp: &str) -> Option<usize> {
+    let mut depth = 1;
+    let mut pos = 0;
+    while depth > 0 {
+        pos += xmp[pos..].find('<')?;
+        let rest = &xmp[pos..];
+        let close = [("<!--", "-->"), ("<![CDATA[", "]]>"), ("<?", "?>")]
+            .iter()
+            .find(|(open, _)| rest.starts_with(open))
+            .map(|(_, close)| close);
+        if let Some(close) = close {
+            pos += rest.find(close)? + close.len();
+        } else if rest.starts_with("</") {
+            depth -= 1;
+            pos += rest.find('>')? + 1;
+        } else {
+            let tag = Tag::parse(rest)?;
+            if !tag.self_closing {
+                depth += 1;
+            }
+            pos += tag.len;
+        }
+    }
+    Some(pos)
+}
+
+/// A start tag of an element
+struct Tag<'a> {
+    name: &'a str,
+    /// The text of each attribute including its leading whitespace, along with its name
+    attributes: Vec<(&'a str, &'a str)>,
+    /// The text after the attributes, either `>` or `/>` with any leading whitespace
+    end: &'a str,
+    self_closing: bool,
+    /// The length of the whole tag
+    len: usize,
+}
+
+impl<'a> Tag<'a> {
+    /// Parse a start tag at the beginning of the text
+    fn parse(xmp: &'a str) -> Option<Self> {
+        let is_name_end = |c: char| c.is_whitespace() || c == '/' || c == '>' || c == '=';
+        let name_len = xmp[1..].find(is_name_end)?;
+        let name = &xmp[1..=name_len];
+        if name.is_empty() {
+            return None;
+        }
+        let mut pos = name_len + 1;
+        let mut attributes = Vec::new();
+        loop {
+            let start = pos;
+            pos += xmp[pos..].find(|c: char| !c.is_whitespace())?;
+            let rest = &xmp[pos..];
+            if rest.starts_with('>') || rest.starts_with("/>") {
+                let self_closing = rest.starts_with('/');
+                let len = pos + if self_closing { 2 } else { 1 };
+                return Some(Self {
+                    name,
+                    attributes,
+                    end: &xmp[start..len],
+                    self_closing,
+                    len,
+                });
+            }
+            let attr_name = &rest[..rest.find(is_name_end)?];
+            pos += attr_name.len();
+            pos += xmp[pos..].find(|c: char| !c.is_whitespace())?;
+            if !xmp[pos..].starts_with('=') || attr_name.is_empty() {
+                return None;
+            }
+            pos += 1;
+            pos += xmp[pos..].find(|c: char| !c.is_whitespace())?;
+            let quote = xmp[pos..]
+                .chars()
+                .next()
+                .filter(|&c| c == '"' || c == '\'')?;
+            pos += 1;
+            pos += xmp[pos..].find(quote)? + 1;
+            attributes.push((&xmp[start..pos], attr_name));
+        }
+    }
+}
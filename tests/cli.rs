@@ -0,0 +1,131 @@
+#![cfg(feature = "binary")]
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+use oxipng::*;
+
+/// Run the binary on an input file, writing to an output named after the test
+fn run(input: &str, name: &str, args: &[&str]) -> (Output, Option<Vec<u8>>) {
+    let output_path = Path::new(input).with_extension(format!("{name}.out.png"));
+    let output = Command::new(env!("CARGO_BIN_EXE_oxipng"))
+        .args(["-o", "0", "--out"])
+        .arg(&output_path)
+        .args(args)
+        .arg(input)
+        .output()
+        .unwrap();
+    let data = fs::read(&output_path).ok();
+    fs::remove_file(&output_path).ok();
+    (output, data)
+}
+
+fn run_metadata(input: &str, name: &str, args: &[&str]) -> Vec<ChunkMetadata> {
+    let (output, data) = run(input, name, args);
+    assert!(output.status.success(), "{output:?}");
+    read_metadata(&data.unwrap()).unwrap()
+}
+
+#[test]
+fn set_text() {
+    let metadata = run_metadata(
+        "tests/files/fully_optimized.png",
+        "set_text",
+        &[
+            "--set-text",
+            "Author=Zoë",
+            "--set-text",
+            "Title=Łódź",
+            "--set-itxt",
+            "Comment=plain",
+        ],
+    );
+    assert_eq!(
+        metadata[0],
+        ChunkMetadata::Text {
+            keyword: "Author".to_owned(),
+            text: "Zoë".to_owned(),
+        }
+    );
+    // Text that isn't Latin-1 can only be stored in an iTXt chunk
+    assert!(
+        matches!(&metadata[1], ChunkMetadata::InternationalText { keyword, text, .. }
+        if keyword == "Title" && text == "Łódź")
+    );
+    assert!(
+        matches!(&metadata[2], ChunkMetadata::InternationalText { keyword, text, .. }
+        if keyword == "Comment" && text == "plain")
+    );
+}
+
+#[test]
+fn set_text_invalid_keyword() {
+    for (i, arg) in [" Author=x", "Author", "=x", "Łódź=x"].iter().enumerate() {
+        let name = format!("set_text_invalid_keyword_{i}");
+        let (output, data) = run(
+            "tests/files/fully_optimized.png",
+            &name,
+            &["--set-text", arg],
+        );
+        assert!(!output.status.success());
+        assert!(data.is_none());
+        // The keyword is rejected before any file is processed
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!stderr.contains("Processing"), "{stderr}");
+    }
+}
+
+#[test]
+fn set_phys() {
+    let metadata = run_metadata(
+        "tests/files/fully_optimized.png",
+        "set_phys_dpi",
+        &["--set-phys", "72dpi"],
+    );
+    assert_eq!(
+        metadata,
+        [ChunkMetadata::PhysicalDimensions {
+            x: 2835,
+            y: 2835,
+            unit: PhysUnit::Meter,
+        }]
+    );
+
+    let metadata = run_metadata(
+        "tests/files/fully_optimized.png",
+        "set_phys_ratio",
+        &["--set-phys", "2:1"],
+    );
+    assert_eq!(
+        metadata,
+        [ChunkMetadata::PhysicalDimensions {
+            x: 2,
+            y: 1,
+            unit: PhysUnit::Unknown,
+        }]
+    );
+
+    let (output, _) = run(
+        "tests/files/fully_optimized.png",
+        "set_phys_invalid",
+        &["--set-phys", "72"],
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn set_srgb() {
+    let metadata = run_metadata(
+        "tests/files/profile_adobe_rgb_allow_gray.png",
+        "set_srgb",
+        &["--set-srgb=relative"],
+    );
+    // The ICC profile is replaced by the sRGB chunk
+    assert!(!metadata
+        .iter()
+        .any(|m| matches!(m, ChunkMetadata::IccProfile { .. })));
+    assert!(metadata.contains(&ChunkMetadata::Srgb(1)));
+}
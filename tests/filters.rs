@@ -855,3 +855,82 @@ fn filter_5_for_palette_1() {
         BitDepth::One,
     );
 }
+
+/// Naive implementation of the standard filters, for comparison
+fn reference_filter(filter: RowFilter, bpp: usize, data: &[u8], prev_line: &[u8]) -> Vec<u8> {
+    let mut buf = vec![filter as u8];
+    for i in 0..data.len() {
+        let a = if i >= bpp {
+            i16::from(data[i - bpp])
+        } else {
+            0
+        };
+        let b = i16::from(prev_line[i]);
+        let c = if i >= bpp {
+            i16::from(prev_line[i - bpp])
+        } else {
+            0
+        };
+        let predictor = match filter {
+            RowFilter::Sub => a,
+            RowFilter::Up => b,
+            RowFilter::Average => (a + b) / 2,
+            RowFilter::Paeth => {
+                let p = a + b - c;
+                let (pa, pb, pc) = ((p - a).abs(), (p - b).abs(), (p - c).abs());
+                if pa <= pb && pa <= pc {
+                    a
+                } else if pb <= pc {
+                    b
+                } else {
+                    c
+                }
+            }
+            _ => 0,
+        };
+        buf.push(data[i].wrapping_sub(predictor as u8));
+    }
+    buf
+}
+
+#[test]
+fn standard_filters_round_trip() {
+    let files = [
+        "tests/files/rgba_16_should_be_rgba_16.png",
+        "tests/files/rgba_8_should_be_rgba_8.png",
+        "tests/files/rgb_16_should_be_rgb_16.png",
+        "tests/files/rgb_8_should_be_rgb_8.png",
+        "tests/files/grayscale_alpha_8_should_be_grayscale_alpha_8.png",
+        "tests/files/grayscale_8_should_be_grayscale_8.png",
+        "tests/files/palette_4_should_be_palette_4.png",
+    ];
+    for file in files {
+        let png = PngData::new(Path::new(file), &Options::default()).unwrap();
+        let raw = &png.raw;
+        assert_eq!(raw.ihdr.interlaced, Interlacing::None);
+        let bpp = raw.ihdr.bpp().div_ceil(8);
+        let line_len = raw.data.len() / raw.ihdr.height as usize;
+        for filter in [
+            RowFilter::Sub,
+            RowFilter::Up,
+            RowFilter::Average,
+            RowFilter::Paeth,
+        ] {
+            let mut expected = Vec::new();
+            let mut prev_line = vec![0; line_len];
+            for line in raw.data.chunks(line_len) {
+                expected.extend(reference_filter(filter, bpp, line, &prev_line));
+                prev_line.copy_from_slice(line);
+            }
+            let filtered = raw.filter_image(filter, false);
+            assert!(filtered == expected, "{file}: {filter} filter mismatch");
+
+            let compressed = deflate(&filtered, 1, None).unwrap();
+            let unfiltered = PngImage::new(raw.ihdr.clone(), &compressed).unwrap();
+            assert!(
+                unfiltered.data == raw.data,
+                "{file}: {filter} unfilter mismatch"
+            );
+        }
+    }
+}
@@ -11,6 +11,7 @@ use oxipng::{internal_tests::*, *};
 const GRAY: u8 = 0;
 const RGB: u8 = 2;
 const INDEXED: u8 = 3;
+const RGBA: u8 = 6;
 
 fn get_opts(input: &Path) -> (OutFile, Options) {
     let options = Options {
@@ -204,6 +205,20 @@ fn count_chunk(png: &PngData, name: &[u8; 4]) -> usize {
         .count()
 }
 
+/// Assert that the iCCP chunk contains a grayscale profile with a gray tone curve
+fn assert_gray_profile(png: &PngData) {
+    let chunk = png
+        .aux_chunks
+        .iter()
+        .find(|chunk| &chunk.name == b"iCCP")
+        .unwrap();
+    let name_len = chunk.data.iter().position(|&b| b == 0).unwrap();
+    let profile = inflate(&chunk.data[name_len + 2..], 1 << 20).unwrap();
+    assert_eq!(&profile[16..20], b"GRAY");
+    let tag_count = u32::from_be_bytes(profile[128..132].try_into().unwrap()) as usize;
+    assert!((0..tag_count).any(|i| &profile[132 + 12 * i..136 + 12 * i] == b"kTRC"));
+}
+
 #[test]
 fn strip_chunks_list() {
     let input = PathBuf::from("tests/files/strip_chunks_list.png");
@@ -312,11 +327,35 @@ fn strip_chunks_none() {
     );
 }
 
+#[test]
+fn alpha_fill() {
+    let input = PathBuf::from("tests/files/rgba_8_reduce_alpha.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.optimize_alpha = true;
+    opts.alpha_fill = indexset! {
+        AlphaFill::Black,
+        AlphaFill::Left,
+        AlphaFill::Up,
+        AlphaFill::Predictor,
+        AlphaFill::Nearest
+    };
+
+    test_it_converts(
+        input,
+        &output,
+        &opts,
+        RGBA,
+        BitDepth::Eight,
+        RGBA,
+        BitDepth::Eight,
+    );
+}
+
 #[test]
 fn interlacing_0_to_1() {
     let input = PathBuf::from("tests/files/interlacing_0_to_1.png");
     let (output, mut opts) = get_opts(&input);
-    opts.interlace = Some(Interlacing::Adam7);
+    opts.interlace = InterlaceMode::Set(Interlacing::Adam7);
 
     test_it_converts_callbacks(
         input,
@@ -339,7 +378,7 @@ fn interlacing_0_to_1() {
 fn interlacing_1_to_0() {
     let input = PathBuf::from("tests/files/interlacing_1_to_0.png");
     let (output, mut opts) = get_opts(&input);
-    opts.interlace = Some(Interlacing::None);
+    opts.interlace = InterlaceMode::Set(Interlacing::None);
 
     test_it_converts_callbacks(
         input,
@@ -362,7 +401,7 @@ fn interlacing_1_to_0() {
 fn interlacing_0_to_1_small_files() {
     let input = PathBuf::from("tests/files/interlacing_0_to_1_small_files.png");
     let (output, mut opts) = get_opts(&input);
-    opts.interlace = Some(Interlacing::Adam7);
+    opts.interlace = InterlaceMode::Set(Interlacing::Adam7);
 
     test_it_converts_callbacks(
         input,
@@ -385,7 +424,7 @@ fn interlacing_0_to_1_small_files() {
 fn interlacing_1_to_0_small_files() {
     let input = PathBuf::from("tests/files/interlacing_1_to_0_small_files.png");
     let (output, mut opts) = get_opts(&input);
-    opts.interlace = Some(Interlacing::None);
+    opts.interlace = InterlaceMode::Set(Interlacing::None);
 
     test_it_converts_callbacks(
         input,
@@ -404,11 +443,57 @@ fn interlacing_1_to_0_small_files() {
     );
 }
 
+#[test]
+fn interlacing_auto() {
+    let input = PathBuf::from("tests/files/interlacing_1_to_0.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.interlace = InterlaceMode::Auto { tolerance: 0.0 };
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        RGB,
+        BitDepth::Eight,
+        |png| {
+            assert_eq!(png.raw.ihdr.interlaced, Interlacing::Adam7);
+        },
+        |png| {
+            assert_eq!(png.raw.ihdr.interlaced, Interlacing::None);
+        },
+    );
+}
+
+#[test]
+fn interlacing_auto_tolerance() {
+    let input = PathBuf::from("tests/files/interlacing_0_to_1.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.interlace = InterlaceMode::Auto { tolerance: 50.0 };
+
+    test_it_converts_callbacks(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        RGB,
+        BitDepth::Eight,
+        |png| {
+            assert_eq!(png.raw.ihdr.interlaced, Interlacing::None);
+        },
+        |png| {
+            assert_eq!(png.raw.ihdr.interlaced, Interlacing::Adam7);
+        },
+    );
+}
+
 #[test]
 fn interlaced_0_to_1_other_filter_mode() {
     let input = PathBuf::from("tests/files/interlaced_0_to_1_other_filter_mode.png");
     let (output, mut opts) = get_opts(&input);
-    opts.interlace = Some(Interlacing::Adam7);
+    opts.interlace = InterlaceMode::Set(Interlacing::Adam7);
     opts.filter = indexset! {RowFilter::Paeth};
 
     test_it_converts_callbacks(
@@ -428,6 +513,43 @@ fn interlaced_0_to_1_other_filter_mode() {
     );
 }
 
+#[test]
+fn exhaustive() {
+    let input = PathBuf::from("tests/files/rgba_8_should_be_palette_8.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.exhaustive = true;
+    opts.filter = indexset! {RowFilter::None, RowFilter::Sub, RowFilter::Bigrams};
+
+    test_it_converts(
+        input,
+        &output,
+        &opts,
+        RGBA,
+        BitDepth::Eight,
+        INDEXED,
+        BitDepth::Eight,
+    );
+}
+
+#[test]
+fn brute_model_settings() {
+    let input = PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.filter = indexset! {RowFilter::BruteModel};
+    opts.brute_window = 1024;
+    opts.brute_level = 1;
+
+    test_it_converts(
+        input,
+        &output,
+        &opts,
+        RGB,
+        BitDepth::Eight,
+        RGB,
+        BitDepth::Eight,
+    );
+}
+
 #[test]
 fn preserve_attrs() {
     let input = PathBuf::from("tests/files/preserve_attrs.png");
@@ -500,6 +622,55 @@ fn fix_errors() {
     );
 }
 
+#[test]
+fn apng_errors() {
+    let input = PathBuf::from("tests/files/apng_invalid.png");
+    let (output, opts) = get_opts(&input);
+
+    let result = oxipng::optimize(&InFile::Path(input), &output, &opts);
+    assert!(matches!(result, Err(PngError::APNGOutOfOrder)));
+}
+
+#[test]
+fn fix_apng_errors() {
+    let input = PathBuf::from("tests/files/apng_invalid.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.fix_errors = true;
+
+    oxipng::optimize(&InFile::Path(input), &output, &opts).unwrap();
+    let output = output.path().unwrap();
+    let png = PngData::new(output, &opts).unwrap();
+    remove_file(output).ok();
+
+    let actl = png.aux_chunks.iter().find(|c| &c.name == b"acTL").unwrap();
+    assert_eq!(actl.data[0..4], 3u32.to_be_bytes());
+    assert_eq!(png.frames.len(), 2);
+    let ihdr = &png.raw.ihdr;
+    for frame in &png.frames {
+        assert!(frame.x_offset + frame.width <= ihdr.width);
+        assert!(frame.y_offset + frame.height <= ihdr.height);
+    }
+}
+
+#[test]
+fn fix_apng_frame_order() {
+    let input = PathBuf::from("tests/files/apng_out_of_order.png");
+    let (_, mut opts) = get_opts(&input);
+    assert!(matches!(
+        PngData::new(&input, &opts),
+        Err(PngError::APNGOutOfOrder)
+    ));
+
+    // The frame data must be restored to the order of the sequence numbers
+    opts.fix_errors = true;
+    let png = PngData::new(&input, &opts).unwrap();
+    let expected = PngData::new(Path::new("tests/files/apng_full_frames.png"), &opts).unwrap();
+    assert_eq!(png.frames.len(), expected.frames.len());
+    for (frame, expected) in png.frames.iter().zip(&expected.frames) {
+        assert_eq!(frame.data, expected.data);
+    }
+}
+
 #[test]
 fn no_grayscale_change() {
     let input = PathBuf::from("tests/files/rgb_8_should_be_grayscale_8.png");
@@ -518,8 +689,8 @@ fn no_grayscale_change() {
 }
 
 #[test]
-fn profile_adobe_rgb_disallow_gray() {
-    let input = PathBuf::from("tests/files/profile_adobe_rgb_disallow_gray.png");
+fn profile_adobe_rgb_allow_gray() {
+    let input = PathBuf::from("tests/files/profile_adobe_rgb_allow_gray.png");
     let (output, mut opts) = get_opts(&input);
     opts.strip = StripChunks::Safe;
 
@@ -529,13 +700,14 @@ fn profile_adobe_rgb_disallow_gray() {
         &opts,
         RGB,
         BitDepth::Eight,
-        INDEXED,
+        GRAY,
         BitDepth::Eight,
         |png| {
             assert_eq!(count_chunk(png, b"iCCP"), 1);
         },
         |png| {
             assert_eq!(count_chunk(png, b"iCCP"), 1);
+            assert_gray_profile(png);
         },
     );
 }
@@ -559,14 +731,14 @@ fn profile_srgb_allow_gray() {
         },
         |png| {
             assert_eq!(count_chunk(png, b"iCCP"), 0);
-            assert_eq!(count_chunk(png, b"sRGB"), 0);
+            assert_eq!(count_chunk(png, b"sRGB"), 1);
         },
     );
 }
 
 #[test]
-fn profile_srgb_no_strip_disallow_gray() {
-    let input = PathBuf::from("tests/files/profile_srgb_no_strip_disallow_gray.png");
+fn profile_srgb_no_strip_allow_gray() {
+    let input = PathBuf::from("tests/files/profile_srgb_no_strip_allow_gray.png");
     let (output, mut opts) = get_opts(&input);
     opts.strip = StripChunks::None;
 
@@ -576,13 +748,15 @@ fn profile_srgb_no_strip_disallow_gray() {
         &opts,
         RGB,
         BitDepth::Eight,
-        INDEXED,
+        GRAY,
         BitDepth::Eight,
         |png| {
             assert_eq!(count_chunk(png, b"iCCP"), 1);
         },
         |png| {
             assert_eq!(count_chunk(png, b"iCCP"), 1);
+            assert_gray_profile(png);
+            assert_eq!(count_chunk(png, b"sRGB"), 0);
         },
     );
 }
@@ -627,6 +801,23 @@ fn no_bit_depth_change() {
     );
 }
 
+#[test]
+fn no_bit_depth_change_16_to_indexed() {
+    let input = PathBuf::from("tests/files/rgba_16_should_be_palette_8.png");
+    let (output, mut opts) = get_opts(&input);
+    opts.bit_depth_reduction = false;
+
+    test_it_converts(
+        input,
+        &output,
+        &opts,
+        RGBA,
+        BitDepth::Sixteen,
+        INDEXED,
+        BitDepth::Eight,
+    );
+}
+
 #[test]
 fn scale_16() {
     let input = PathBuf::from("tests/files/rgb_16_should_be_rgb_16.png");
@@ -15,7 +15,7 @@ fn get_opts(input: &Path) -> (OutFile, oxipng::Options) {
     let mut options = oxipng::Options {
         force: true,
         fast_evaluation: false,
-        interlace: None,
+        interlace: InterlaceMode::Keep,
         ..Default::default()
     };
     let mut filter = IndexSet::new();
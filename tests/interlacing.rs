@@ -31,7 +31,7 @@ fn test_it_converts(
     let input = PathBuf::from(input);
     let (output, mut opts) = get_opts(&input);
     let png = PngData::new(&input, &opts).unwrap();
-    opts.interlace = Some(interlace);
+    opts.interlace = InterlaceMode::Set(interlace);
     assert_eq!(png.raw.ihdr.color_type.png_header_code(), color_type_in);
     assert_eq!(png.raw.ihdr.bit_depth, bit_depth_in);
     assert_eq!(
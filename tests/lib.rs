@@ -1,4 +1,4 @@
-use std::{fs, fs::File, io::prelude::*};
+use std::{fs, fs::File, io::prelude::*, time::Duration};
 
 use oxipng::*;
 
@@ -75,6 +75,102 @@ fn optimize_apng() {
     assert!(result.is_ok());
 }
 
+#[test]
+fn optimize_apng_frames_independently() {
+    let file = fs::read("tests/files/apng_file.png").unwrap();
+    let opts = Options::from_preset(2);
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    let original = internal_tests::PngData::from_slice(&file, &opts).unwrap();
+    let png = internal_tests::PngData::from_slice(&result, &opts).unwrap();
+    assert_eq!(png.frames.len(), original.frames.len());
+    // Frames are only replaced when their own compression improves
+    for (frame, original_frame) in png.frames.iter().zip(&original.frames) {
+        assert!(frame.data.len() <= original_frame.data.len());
+    }
+}
+
+#[test]
+fn optimize_apng_rediffs_frames() {
+    let file = fs::read("tests/files/apng_full_frames.png").unwrap();
+    let opts = Options::default();
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    assert!(result.len() < file.len());
+    let png = internal_tests::PngData::from_slice(&result, &opts).unwrap();
+    assert!(!png.frames.is_empty());
+    // Each frame should be reduced to the region around the moving square
+    for frame in &png.frames {
+        assert!(frame.width < png.raw.ihdr.width);
+        assert!(frame.height < png.raw.ihdr.height);
+    }
+}
+
+#[test]
+fn optimize_apng_single_frame() {
+    let file = fs::read("tests/files/apng_single_frame.png").unwrap();
+    let opts = Options::default();
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    let png = internal_tests::PngData::from_slice(&result, &opts).unwrap();
+    assert!(png.frames.is_empty());
+    assert!(!png
+        .aux_chunks
+        .iter()
+        .any(|c| &c.name == b"acTL" || &c.name == b"fcTL"));
+}
+
+#[test]
+fn optimize_apng_duplicate_frames() {
+    let file = fs::read("tests/files/apng_duplicate_frames.png").unwrap();
+    let opts = Options::default();
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    let png = internal_tests::PngData::from_slice(&result, &opts).unwrap();
+    // The duplicate of the default image and the transparent frame are merged into the previous
+    let actl = png.aux_chunks.iter().find(|c| &c.name == b"acTL").unwrap();
+    assert_eq!(actl.data[0..4], 3u32.to_be_bytes());
+    assert_eq!(png.frames.len(), 2);
+    let delays: Vec<_> = png
+        .frames
+        .iter()
+        .map(|f| (f.delay_num, f.delay_den))
+        .collect();
+    assert_eq!(delays, [(1, 5), (1, 10)]);
+}
+
+#[test]
+fn optimize_apng_reduced_without_recoding() {
+    let file = fs::read("tests/files/apng_bkgd.png").unwrap();
+    // Without recoding, the reduced image is kept even though no better result is found
+    let opts = Options {
+        idat_recoding: false,
+        ..Options::default()
+    };
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    let png = internal_tests::PngData::from_slice(&result, &opts).unwrap();
+    assert!(matches!(png.raw.ihdr.color_type, ColorType::Indexed { .. }));
+    // The bKGD chunk no longer matches the color type, so it must have been removed
+    assert!(!png.aux_chunks.iter().any(|c| &c.name == b"bKGD"));
+}
+
+#[test]
+fn optimize_apng_bkgd_timeout() {
+    let file = fs::read("tests/files/apng_bkgd.png").unwrap();
+    let opts = Options {
+        timeout: Some(Duration::ZERO),
+        ..Options::default()
+    };
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    let png = internal_tests::PngData::from_slice(&result, &opts).unwrap();
+    // The bKGD chunk may only be kept if the color type was not changed
+    let has_bkgd = png.aux_chunks.iter().any(|c| &c.name == b"bKGD");
+    let reduced = !matches!(png.raw.ihdr.color_type, ColorType::RGBA);
+    assert!(!(has_bkgd && reduced));
+}
+
 #[test]
 fn optimize_srgb_icc() {
     let file = fs::read("tests/files/badsrgb.png").unwrap();
@@ -87,3 +183,45 @@ fn optimize_srgb_icc() {
     let result = oxipng::optimize_from_memory(&file, &opts);
     assert!(result.unwrap().len() < 1000);
 }
+
+#[test]
+fn optimize_srgb_gama_chrm() {
+    let file = fs::read("tests/files/srgb_gama_chrm.png").unwrap();
+    let names = |opts: &Options| {
+        let result = oxipng::optimize_from_memory(&file, opts).unwrap();
+        let png = internal_tests::PngData::from_slice(&result, opts).unwrap();
+        // Grayscale reduction is still allowed
+        assert!(matches!(
+            png.raw.ihdr.color_type,
+            ColorType::Grayscale { .. }
+        ));
+        png.aux_chunks.iter().map(|c| c.name).collect::<Vec<_>>()
+    };
+
+    // The chunks are only replaced when stripping is enabled
+    let mut opts = Options::default();
+    assert_eq!(names(&opts), [*b"gAMA", *b"cHRM", *b"IDAT"]);
+
+    opts.strip = StripChunks::Strip(indexset! {*b"tIME"});
+    assert_eq!(names(&opts), [*b"sRGB", *b"IDAT"]);
+
+    opts.strip = StripChunks::Keep(indexset! {*b"gAMA", *b"cHRM"});
+    assert_eq!(names(&opts), [*b"gAMA", *b"cHRM", *b"IDAT"]);
+
+    // Safe stripping would remove the chunks, but they are replaced first
+    opts.strip = StripChunks::Safe;
+    assert_eq!(names(&opts), [*b"sRGB", *b"IDAT"]);
+
+    // An existing sRGB chunk takes precedence
+    opts.strip = StripChunks::Strip(indexset! {*b"tIME"});
+    opts.metadata = vec![MetadataEdit::Insert(ChunkMetadata::Srgb(1))];
+    assert_eq!(names(&opts), [*b"sRGB", *b"IDAT"]);
+
+    // A different gamma is not equivalent
+    opts.metadata = vec![MetadataEdit::Replace(ChunkMetadata::Gamma(55555))];
+    assert_eq!(names(&opts), [*b"gAMA", *b"cHRM", *b"IDAT"]);
+
+    // Otherwise they are still stripped, except for chunks added by an edit
+    opts.strip = StripChunks::Safe;
+    assert_eq!(names(&opts), [*b"gAMA", *b"IDAT"]);
+}
@@ -0,0 +1,466 @@
+use std::fs;
+
+use oxipng::*;
+
+fn text(keyword: &str, text: &str) -> ChunkMetadata {
+    ChunkMetadata::Text {
+        keyword: keyword.into(),
+        text: text.into(),
+    }
+}
+
+#[test]
+fn read() {
+    let file = fs::read("tests/files/strip_chunks_none.png").unwrap();
+    let metadata = read_metadata(&file).unwrap();
+
+    assert!(
+        matches!(&metadata[0], ChunkMetadata::IccProfile { name, profile }
+        if name == "ICC profile" && profile.len() == 3144)
+    );
+    assert_eq!(metadata[1], text("Software", "Adobe ImageReady"));
+    assert_eq!(metadata[1].keyword(), Some("Software"));
+    assert!(
+        matches!(&metadata[4], ChunkMetadata::InternationalText { keyword, text, .. }
+        if keyword == "XML:com.adobe.xmp" && text.starts_with("<?xpacket"))
+    );
+}
+
+#[test]
+fn read_not_png() {
+    let file = fs::read("tests/files/corrupted_header.png").unwrap();
+    assert!(read_metadata(&file).is_err());
+}
+
+#[test]
+fn insert() {
+    let file = fs::read("tests/files/fully_optimized.png").unwrap();
+    let inserted = vec![
+        text("Title", "Café"),
+        ChunkMetadata::CompressedText {
+            keyword: "Comment".into(),
+            text: "Compressed ".repeat(100),
+        },
+        ChunkMetadata::InternationalText {
+            keyword: "Title".into(),
+            language: "ja".into(),
+            translated_keyword: "タイトル".into(),
+            text: "カフェ".into(),
+            compressed: true,
+        },
+        ChunkMetadata::Exif(b"MM\0\x2a\0\0\0\x08\0\0".to_vec()),
+        ChunkMetadata::PhysicalDimensions {
+            x: 3780,
+            y: 3780,
+            unit: PhysUnit::Meter,
+        },
+        ChunkMetadata::Time {
+            year: 2024,
+            month: 2,
+            day: 29,
+            hour: 12,
+            minute: 30,
+            second: 0,
+        },
+        ChunkMetadata::Gamma(45455),
+    ];
+    let opts = Options {
+        metadata: inserted.iter().cloned().map(MetadataEdit::Insert).collect(),
+        ..Options::default()
+    };
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    // The output must be written even though it is larger
+    assert!(result.len() > file.len());
+    assert_eq!(read_metadata(&result).unwrap(), inserted);
+}
+
+#[test]
+fn replace_and_remove() {
+    let file = fs::read("tests/files/strip_chunks_none.png").unwrap();
+    let opts = Options {
+        metadata: vec![
+            // Replaces the tEXt chunk with an iTXt chunk of the same keyword
+            MetadataEdit::Replace(ChunkMetadata::InternationalText {
+                keyword: "Software".into(),
+                language: String::new(),
+                translated_keyword: String::new(),
+                text: "oxipng".into(),
+                compressed: false,
+            }),
+            MetadataEdit::RemoveText("XML:com.adobe.xmp".into()),
+            MetadataEdit::Remove(*b"iCCP"),
+            MetadataEdit::Replace(ChunkMetadata::Srgb(0)),
+        ],
+        ..Options::default()
+    };
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    let metadata = read_metadata(&result).unwrap();
+    assert_eq!(metadata.len(), 4);
+    assert!(
+        matches!(&metadata[0], ChunkMetadata::InternationalText { keyword, text, .. }
+        if keyword == "Software" && text == "oxipng")
+    );
+    assert_eq!(metadata[1].keyword(), Some("date:create"));
+    assert_eq!(metadata[2].keyword(), Some("date:modify"));
+    assert_eq!(metadata[3], ChunkMetadata::Srgb(0));
+}
+
+#[test]
+fn invalid_edits() {
+    let file = fs::read("tests/files/fully_optimized.png").unwrap();
+    for edit in [
+        MetadataEdit::Insert(text("", "Empty keyword")),
+        MetadataEdit::Insert(text(" Title", "Leading space")),
+        MetadataEdit::Insert(text("Title", "Not Latin-1 ✓")),
+        MetadataEdit::Remove(*b"IDAT"),
+    ] {
+        let opts = Options {
+            metadata: vec![edit],
+            ..Options::default()
+        };
+        assert!(oxipng::optimize_from_memory(&file, &opts).is_err());
+    }
+}
+
+#[test]
+fn raw_image() {
+    let raw = RawImage::new(1, 1, ColorType::RGBA, BitDepth::Eight, vec![0, 0, 0, 255]).unwrap();
+    let opts = Options {
+        metadata: vec![MetadataEdit::Insert(text("Author", "oxipng"))],
+        ..Options::default()
+    };
+
+    let result = raw.create_optimized_png(&opts).unwrap();
+    assert_eq!(read_metadata(&result).unwrap(), [text("Author", "oxipng")]);
+}
+
+fn exif_data(png: &[u8]) -> Vec<u8> {
+    read_metadata(png)
+        .unwrap()
+        .into_iter()
+        .find_map(|m| match m {
+            ChunkMetadata::Exif(data) => Some(data),
+            _ => None,
+        })
+        .unwrap()
+}
+
+fn contains(data: &[u8], value: &[u8]) -> bool {
+    data.windows(value.len()).any(|w| w == value)
+}
+
+#[test]
+fn strip_exif_gps() {
+    let file = fs::read("tests/files/exif_private.png").unwrap();
+    let opts = Options {
+        strip_exif: indexset! {ExifGroup::Gps},
+        ..Options::default()
+    };
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    let exif = exif_data(&result);
+    // The GPS IFD is cleared, but the data is not moved
+    assert_eq!(exif.len(), exif_data(&file).len());
+    assert!(!contains(&exif, &0x8825u16.to_le_bytes()));
+    assert!(!contains(&exif, b"N\0\0\0"));
+    assert!(contains(&exif, b"SN123456789"));
+    assert!(contains(&exif, b"(c) Example Copyright"));
+}
+
+#[test]
+fn strip_exif_all() {
+    let file = fs::read("tests/files/exif_private.png").unwrap();
+    let opts = Options {
+        strip_exif: indexset! {ExifGroup::Gps, ExifGroup::Serial, ExifGroup::Thumbnail},
+        ..Options::default()
+    };
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    let exif = exif_data(&result);
+    // The thumbnail is at the end of the data, so it can be truncated
+    assert!(exif.len() < 200);
+    assert!(!contains(&exif, b"SN123456789"));
+    assert!(!contains(&exif, b"LENS98765"));
+    assert!(contains(&exif, b"2024:02:29 12:30:00"));
+    assert!(contains(&exif, b"(c) Example Copyright"));
+    // The orientation is kept
+    assert!(contains(
+        &exif,
+        &[0x12, 0x01, 0x03, 0x00, 0x01, 0, 0, 0, 0x06, 0x00]
+    ));
+}
+
+fn optimize_with_exif(exif: &[u8]) -> Vec<u8> {
+    let file = fs::read("tests/files/fully_optimized.png").unwrap();
+    let opts = Options {
+        metadata: vec![MetadataEdit::Insert(ChunkMetadata::Exif(exif.to_vec()))],
+        strip_exif: indexset! {ExifGroup::Gps},
+        ..Options::default()
+    };
+    oxipng::optimize_from_memory(&file, &opts).unwrap()
+}
+
+#[test]
+fn strip_exif_invalid() {
+    let result = optimize_with_exif(b"II*\0\x08\0\0\0\xff\xff");
+    // The tags can't be found, so the whole chunk is removed
+    assert!(!read_metadata(&result)
+        .unwrap()
+        .iter()
+        .any(|m| matches!(m, ChunkMetadata::Exif(_))));
+}
+
+#[test]
+fn strip_exif_malformed() {
+    let mut exif = exif_data(&fs::read("tests/files/exif_private.png").unwrap());
+    // Give the copyright entry an unknown type, which is kept as is
+    exif[36..38].copy_from_slice(&129u16.to_le_bytes());
+    let result = optimize_with_exif(&exif);
+    let stripped = exif_data(&result);
+    assert!(!contains(&stripped, &0x8825u16.to_le_bytes()));
+    assert!(!contains(&stripped, b"N\0\0\0"));
+    assert!(contains(&stripped, b"(c) Example Copyright"));
+
+    // Point the GPS IFD out of range, so it can't be removed
+    exif[66..70].copy_from_slice(&0xFFFFu32.to_le_bytes());
+    let result = optimize_with_exif(&exif);
+    assert!(!read_metadata(&result)
+        .unwrap()
+        .iter()
+        .any(|m| matches!(m, ChunkMetadata::Exif(_))));
+}
+
+#[test]
+fn strip_exif_repeated_ifds() {
+    // A chain of IFDs where every entry of each one points to the next, which would take
+    // exponential time to walk if IFDs could be visited more than once
+    const IFDS: usize = 9;
+    const ENTRIES: usize = 16;
+    let ifd_size = 2 + 12 * ENTRIES + 4;
+    let mut exif = b"II*\0\x08\0\0\0".to_vec();
+    for i in 0..IFDS {
+        let next = 8 + (i + 1) * ifd_size;
+        let count = if i + 1 < IFDS { ENTRIES } else { 0 };
+        exif.extend_from_slice(&(count as u16).to_le_bytes());
+        for _ in 0..count {
+            exif.extend_from_slice(&0x8769u16.to_le_bytes());
+            exif.extend_from_slice(&4u16.to_le_bytes());
+            exif.extend_from_slice(&1u32.to_le_bytes());
+            exif.extend_from_slice(&(next as u32).to_le_bytes());
+        }
+        exif.extend_from_slice(&[0; 4]);
+        exif.resize(next, 0);
+    }
+    let result = optimize_with_exif(&exif);
+    assert!(!read_metadata(&result)
+        .unwrap()
+        .iter()
+        .any(|m| matches!(m, ChunkMetadata::Exif(_))));
+}
+
+fn xmp_text(png: &[u8]) -> (String, bool) {
+    read_metadata(png)
+        .unwrap()
+        .into_iter()
+        .find_map(|m| match m {
+            ChunkMetadata::InternationalText {
+                keyword,
+                text,
+                compressed,
+                ..
+            } if keyword == "XML:com.adobe.xmp" => Some((text, compressed)),
+            _ => None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn strip_xmp() {
+    let file = fs::read("tests/files/xmp_history.png").unwrap();
+    let opts = Options {
+        strip_xmp: indexset! {
+            "photoshop:History".to_owned(),
+            "xmpMM:History".to_owned(),
+            "xmp:Thumbnails".to_owned(),
+        },
+        ..Options::default()
+    };
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    let (xmp, compressed) = xmp_text(&result);
+    assert!(compressed);
+    assert!(result.len() < file.len() / 4);
+    assert!(!xmp.contains("History"));
+    assert!(!xmp.contains("Thumbnails"));
+    assert!(!xmp.contains("stEvt:action"));
+    assert!(xmp.contains("<dc:rights>"));
+    assert!(xmp.contains("Copyright (c) Example Rights Holder"));
+    assert!(xmp.contains("<cc:license rdf:resource="));
+    assert!(xmp.contains("photoshop:ColorMode=\"3\""));
+    assert!(xmp.ends_with("<?xpacket end=\"w\"?>"));
+}
+
+#[test]
+fn strip_xmp_namespace() {
+    let file = fs::read("tests/files/xmp_history.png").unwrap();
+    let opts = Options {
+        strip_xmp: indexset! {"photoshop:*".to_owned(), "xmpMM:*".to_owned()},
+        ..Options::default()
+    };
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    let (xmp, _) = xmp_text(&result);
+    assert!(!xmp.contains("photoshop:ColorMode"));
+    assert!(!xmp.contains("photoshop:History"));
+    assert!(!xmp.contains("xmpMM:InstanceID"));
+    assert!(!xmp.contains("xmpMM:History"));
+    // Namespace declarations are kept, as are other properties
+    assert!(xmp.contains("xmlns:photoshop="));
+    assert!(xmp.contains("xmp:CreatorTool=\"Example Editor 1.0\"\n    xmpRights:Marked"));
+    assert!(xmp.contains("<xmp:Thumbnails>"));
+}
+
+#[test]
+fn strip_xmp_invalid() {
+    let file = fs::read("tests/files/fully_optimized.png").unwrap();
+    let xmp = ChunkMetadata::InternationalText {
+        keyword: "XML:com.adobe.xmp".into(),
+        language: String::new(),
+        translated_keyword: String::new(),
+        text: "<x:xmpmeta><xmpMM:History attr=unquoted></x:xmpmeta>".into(),
+        compressed: false,
+    };
+    let opts = Options {
+        metadata: vec![MetadataEdit::Insert(xmp.clone())],
+        strip_xmp: indexset! {"xmpMM:History".to_owned()},
+        ..Options::default()
+    };
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    assert_eq!(read_metadata(&result).unwrap(), [xmp]);
+}
+
+fn text_keywords(png: &[u8]) -> Vec<String> {
+    read_metadata(png)
+        .unwrap()
+        .iter()
+        .filter_map(|m| m.keyword().map(str::to_owned))
+        .collect()
+}
+
+#[test]
+fn strip_text() {
+    let file = fs::read("tests/files/strip_chunks_none.png").unwrap();
+    let opts = Options {
+        strip_text: StripText::Strip(indexset! {"Software".to_owned(), "date:create".to_owned()}),
+        ..Options::default()
+    };
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    assert_eq!(text_keywords(&result), ["date:modify", "XML:com.adobe.xmp"]);
+}
+
+#[test]
+fn keep_text() {
+    let file = fs::read("tests/files/strip_chunks_none.png").unwrap();
+    let opts = Options {
+        strip_text: StripText::Keep(
+            indexset! {"Software".to_owned(), "XML:com.adobe.xmp".to_owned()},
+        ),
+        ..Options::default()
+    };
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    assert_eq!(text_keywords(&result), ["Software", "XML:com.adobe.xmp"]);
+    // Other chunks are unaffected
+    assert!(read_metadata(&result)
+        .unwrap()
+        .iter()
+        .any(|m| matches!(m, ChunkMetadata::IccProfile { .. })));
+}
+
+#[test]
+fn keep_text_overrides_strip() {
+    let file = fs::read("tests/files/strip_chunks_none.png").unwrap();
+    let opts = Options {
+        strip: StripChunks::All,
+        strip_text: StripText::Keep(indexset! {"Software".to_owned()}),
+        ..Options::default()
+    };
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    assert_eq!(
+        read_metadata(&result).unwrap(),
+        [text("Software", "Adobe ImageReady")]
+    );
+}
+
+#[test]
+fn insert_before_palette() {
+    let file = fs::read("tests/files/filter_0_for_palette_4.png").unwrap();
+    let opts = Options {
+        metadata: vec![
+            MetadataEdit::Replace(ChunkMetadata::PhysicalDimensions {
+                x: 11811,
+                y: 11811,
+                unit: PhysUnit::Meter,
+            }),
+            MetadataEdit::Replace(ChunkMetadata::Srgb(0)),
+        ],
+        ..Options::default()
+    };
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    let position = |name: &[u8]| result.windows(4).position(|w| w == name).unwrap();
+    assert!(position(b"pHYs") < position(b"PLTE"));
+    assert!(position(b"sRGB") < position(b"PLTE"));
+}
+
+#[test]
+fn hdr_chunks() {
+    let file = fs::read("tests/files/hdr_chunks.png").unwrap();
+    let opts = Options {
+        strip: StripChunks::Safe,
+        scale_16: true,
+        ..Options::default()
+    };
+
+    let result = oxipng::optimize_from_memory(&file, &opts).unwrap();
+    // The chunks are kept and moved before the image data
+    let position = |name: &[u8]| result.windows(4).position(|w| w == name).unwrap();
+    assert!(position(b"mDCV") < position(b"IDAT"));
+    assert!(position(b"cICP") < position(b"IDAT"));
+    assert!(position(b"cLLI") < position(b"IDAT"));
+    assert_eq!(
+        read_metadata(&result).unwrap(),
+        [
+            ChunkMetadata::MasteringDisplayColorVolume {
+                primaries: [(35400, 14600), (8500, 39850), (6550, 2300)],
+                white_point: (15635, 16450),
+                max_luminance: 10_000_000,
+                min_luminance: 50,
+            },
+            ChunkMetadata::CodingIndependentCodePoints {
+                color_primaries: 9,
+                transfer_function: 16,
+                matrix_coefficients: 0,
+                full_range: true,
+            },
+            ChunkMetadata::ContentLightLevel {
+                max_content: 10_000_000,
+                max_frame_average: 4_000_000,
+            },
+        ]
+    );
+    // Neither grayscale reduction nor scaling should be applied to PQ content
+    let png = internal_tests::PngData::from_slice(&result, &opts).unwrap();
+    assert_eq!(
+        png.raw.ihdr.color_type,
+        ColorType::RGB {
+            transparent_color: None
+        }
+    );
+    assert_eq!(png.raw.ihdr.bit_depth, BitDepth::Sixteen);
+}
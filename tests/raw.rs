@@ -94,3 +94,173 @@ fn incorrect_length() {
     )
     .expect_err("Expected incorrect data length");
 }
+
+fn row_filters_image() -> RawImage {
+    RawImage::new(
+        4,
+        4,
+        ColorType::RGB {
+            transparent_color: None,
+        },
+        BitDepth::Eight,
+        (0..48).map(|i| i * 5).collect(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn row_filters() {
+    let filters = vec![
+        RowFilter::Sub,
+        RowFilter::Up,
+        RowFilter::Average,
+        RowFilter::Paeth,
+    ];
+    let opts = Options {
+        row_filters: Some(filters.clone()),
+        ..get_opts()
+    };
+
+    let output = row_filters_image().create_optimized_png(&opts).unwrap();
+
+    let new = PngData::from_slice(&output, &opts).unwrap();
+    let data = inflate(&new.idat_data, new.raw.ihdr.raw_data_size()).unwrap();
+    let filtered = PngImage {
+        ihdr: new.raw.ihdr.clone(),
+        data,
+    };
+    let used: Vec<_> = filtered.scan_lines(true).map(|line| line.filter).collect();
+    assert_eq!(used, filters.iter().map(|&f| f as u8).collect::<Vec<_>>());
+}
+
+#[test]
+fn row_filters_interlaced() {
+    let opts = Options {
+        interlace: InterlaceMode::Set(Interlacing::Adam7),
+        // A 4x4 image has 7 rows across the interlacing passes
+        row_filters: Some(vec![RowFilter::Sub; 7]),
+        ..get_opts()
+    };
+
+    row_filters_image().create_optimized_png(&opts).unwrap();
+}
+
+#[test]
+fn row_filters_incorrect_length() {
+    let opts = Options {
+        row_filters: Some(vec![RowFilter::Sub; 3]),
+        ..get_opts()
+    };
+
+    row_filters_image()
+        .create_optimized_png(&opts)
+        .expect_err("Expected incorrect number of row filters");
+}
+
+#[test]
+fn row_filters_not_standard() {
+    let opts = Options {
+        row_filters: Some(vec![RowFilter::Bigrams; 4]),
+        ..get_opts()
+    };
+
+    row_filters_image()
+        .create_optimized_png(&opts)
+        .expect_err("Expected invalid row filter");
+}
+
+#[test]
+fn original_filters() {
+    // Each row is a gradient with a different slope, which is best suited to the Sub filter
+    let data = (0..64u32)
+        .flat_map(|y| (0..64u32).flat_map(move |x| [x * y, x * y * 2, x * y * 3]))
+        .map(|v| v as u8)
+        .collect();
+    let raw = RawImage::new(
+        64,
+        64,
+        ColorType::RGB {
+            transparent_color: None,
+        },
+        BitDepth::Eight,
+        data,
+    )
+    .unwrap();
+    let opts = Options {
+        row_filters: Some(vec![RowFilter::Sub; 64]),
+        ..get_opts()
+    };
+    let input = raw.create_optimized_png(&opts).unwrap();
+
+    // The original filters are not among those selected, but should be kept as they are smaller
+    let opts = Options {
+        filter: indexset! { RowFilter::None, RowFilter::Paeth },
+        ..get_opts()
+    };
+    let output = optimize_from_memory(&input, &opts).unwrap();
+
+    let new = PngData::from_slice(&output, &opts).unwrap();
+    let data = inflate(&new.idat_data, new.raw.ihdr.raw_data_size()).unwrap();
+    let filtered = PngImage {
+        ihdr: new.raw.ihdr.clone(),
+        data,
+    };
+    assert!(filtered
+        .scan_lines(true)
+        .all(|line| line.filter == RowFilter::Sub as u8));
+}
+
+fn animation_frame(offset: u32) -> Vec<u8> {
+    // A red square moving over a white background
+    (0..16u32)
+        .flat_map(|y| (0..16u32).map(move |x| (x, y)))
+        .flat_map(|(x, y)| {
+            if (offset..offset + 4).contains(&x) && (6..10).contains(&y) {
+                [255, 0, 0, 255]
+            } else {
+                [255, 255, 255, 255]
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn animation() {
+    let opts = get_opts();
+    let control = FrameControl {
+        delay_num: 1,
+        delay_den: 10,
+        ..Default::default()
+    };
+
+    let mut raw =
+        RawImage::new(16, 16, ColorType::RGBA, BitDepth::Eight, animation_frame(0)).unwrap();
+    raw.set_frame_control(control).unwrap();
+    raw.set_num_plays(3);
+    for offset in [4, 8, 12] {
+        raw.add_frame(16, 16, animation_frame(offset), control)
+            .unwrap();
+    }
+    let output = raw.create_optimized_png(&opts).unwrap();
+
+    let new = PngData::from_slice(&output, &opts).unwrap();
+    let actl = new.aux_chunks.iter().find(|c| &c.name == b"acTL").unwrap();
+    assert_eq!(actl.data, [0, 0, 0, 4, 0, 0, 0, 3]);
+    assert_eq!(new.frames.len(), 3);
+    for frame in &new.frames {
+        assert_eq!((frame.delay_num, frame.delay_den), (1, 10));
+        frame.image(&new.raw.ihdr).unwrap();
+    }
+}
+
+#[test]
+fn animation_frame_out_of_bounds() {
+    let mut raw =
+        RawImage::new(16, 16, ColorType::RGBA, BitDepth::Eight, animation_frame(0)).unwrap();
+    let control = FrameControl {
+        x_offset: 8,
+        ..Default::default()
+    };
+    raw.add_frame(16, 16, animation_frame(4), control)
+        .expect_err("Expected frame outside the bounds of the image");
+}
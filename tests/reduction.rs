@@ -1220,3 +1220,137 @@ fn grayscale_alpha_8_reduce_alpha() {
         BitDepth::Eight,
     );
 }
+
+fn test_it_converts_apng(input: &str, color_type_out: u8, bit_depth_out: BitDepth) {
+    let input = PathBuf::from(input);
+    let (output, opts) = get_opts(&input);
+    let original = PngData::new(&input, &opts).unwrap();
+    assert!(!original.frames.is_empty(), "test file is broken");
+
+    match oxipng::optimize(&InFile::Path(input), &output, &opts) {
+        Ok(_) => (),
+        Err(x) => panic!("{}", x),
+    };
+    let output = output.path().unwrap();
+    let png = match PngData::new(output, &opts) {
+        Ok(x) => x,
+        Err(x) => {
+            remove_file(output).ok();
+            panic!("{}", x)
+        }
+    };
+    remove_file(output).ok();
+
+    let ihdr = &png.raw.ihdr;
+    assert_eq!(ihdr.color_type.png_header_code(), color_type_out);
+    assert_eq!(ihdr.bit_depth, bit_depth_out);
+    assert_eq!(png.frames.len(), original.frames.len());
+    // Every frame must have been converted to the same format without loss
+    for (frame, original_frame) in png.frames.iter().zip(&original.frames) {
+        let image = frame.image(ihdr).unwrap();
+        let original_image = original_frame.image(&original.raw.ihdr).unwrap();
+        let expected = convert::converted_to_format(
+            &original_image,
+            &ihdr.color_type,
+            ihdr.bit_depth,
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(image.data, expected.data);
+    }
+}
+
+#[test]
+fn apng_rgba_8_should_be_palette_4() {
+    test_it_converts_apng(
+        "tests/files/apng_rgba_8_should_be_palette_4.png",
+        INDEXED,
+        BitDepth::Four,
+    );
+}
+
+#[test]
+fn apng_grayscale_16_should_be_grayscale_4() {
+    test_it_converts_apng(
+        "tests/files/apng_grayscale_16_should_be_grayscale_4.png",
+        GRAYSCALE,
+        BitDepth::Four,
+    );
+}
+
+fn test_it_sorts(sort: fn(&PngImage) -> Option<PngImage>) {
+    let input = PathBuf::from("tests/files/palette_8_should_be_palette_8.png");
+    let (_, opts) = get_opts(&input);
+    let png = PngData::new(&input, &opts).unwrap();
+    let sorted = sort(&png.raw).expect("palette should have been sorted");
+    assert_same_colors(&png.raw, &sorted);
+}
+
+fn assert_same_colors(png: &PngImage, sorted: &PngImage) {
+    let ColorType::Indexed { palette } = &png.ihdr.color_type else {
+        panic!("test file is broken");
+    };
+    let ColorType::Indexed {
+        palette: sorted_palette,
+    } = &sorted.ihdr.color_type
+    else {
+        panic!("sorted image is not indexed");
+    };
+    // The new palette must be a permutation of the original
+    assert_eq!(sorted_palette.len(), palette.len());
+    let mut original_colors = palette.clone();
+    let mut sorted_colors = sorted_palette.clone();
+    original_colors.sort_by_key(|c| (c.r, c.g, c.b, c.a));
+    sorted_colors.sort_by_key(|c| (c.r, c.g, c.b, c.a));
+    assert_eq!(sorted_colors, original_colors);
+    // Every pixel must have the same color as before
+    assert_eq!(sorted.data.len(), png.data.len());
+    for (&a, &b) in sorted.data.iter().zip(&png.data) {
+        assert_eq!(sorted_palette[a as usize], palette[b as usize]);
+    }
+}
+
+#[test]
+fn palette_nearest_neighbor_sort() {
+    test_it_sorts(palette::sorted_palette_nearest_neighbor);
+}
+
+#[test]
+fn palette_frequency_sort() {
+    test_it_sorts(palette::sorted_palette_frequency);
+}
+
+#[test]
+fn palette_annealing_sort() {
+    test_it_sorts(palette::sorted_palette_annealing);
+}
+
+#[test]
+fn palette_mzeng_sort_unconnected() {
+    // The third color is never next to the others, so it has no connections
+    let raw = RawImage::new(
+        4,
+        1,
+        ColorType::Indexed {
+            palette: vec![
+                RGBA8::new(255, 0, 0, 255),
+                RGBA8::new(0, 255, 0, 255),
+                RGBA8::new(0, 0, 255, 255),
+            ],
+        },
+        BitDepth::Eight,
+        vec![0, 1, 0, 1],
+    )
+    .unwrap();
+    let opts = Options {
+        bit_depth_reduction: false,
+        color_type_reduction: false,
+        palette_reduction: false,
+        ..Options::from_preset(0)
+    };
+    let png = PngData::from_slice(&raw.create_optimized_png(&opts).unwrap(), &opts).unwrap();
+
+    let sorted = palette::sorted_palette_mzeng(&png.raw).expect("palette should have been sorted");
+    assert_same_colors(&png.raw, &sorted);
+}
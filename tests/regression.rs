@@ -78,7 +78,7 @@ fn test_it_converts(
 fn issue_42() {
     let input = "tests/files/issue-42.png";
     let (output, mut opts) = get_opts(Path::new(input));
-    opts.interlace = Some(Interlacing::Adam7);
+    opts.interlace = InterlaceMode::Set(Interlacing::Adam7);
     test_it_converts(
         input,
         Some((output, opts)),
@@ -189,7 +189,7 @@ fn issue_175() {
 fn issue_182() {
     let input = "tests/files/issue-182.png";
     let (output, mut opts) = get_opts(Path::new(input));
-    opts.interlace = Some(Interlacing::Adam7);
+    opts.interlace = InterlaceMode::Set(Interlacing::Adam7);
 
     test_it_converts(
         input,
@@ -1,6 +1,10 @@
 use std::{
     fs::remove_file,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
 use oxipng::{internal_tests::*, *};
@@ -122,3 +126,93 @@ fn filter_brute() {
         BitDepth::Eight,
     );
 }
+
+#[test]
+fn filter_brute_model() {
+    test_it_converts(
+        "tests/files/rgba_8_should_be_rgba_8.png",
+        RowFilter::BruteModel,
+        RGBA,
+        BitDepth::Eight,
+        RGBA,
+        BitDepth::Eight,
+    );
+}
+
+#[test]
+fn filter_genetic() {
+    test_it_converts(
+        "tests/files/palette_8_should_be_palette_8.png",
+        RowFilter::Genetic,
+        INDEXED,
+        BitDepth::Eight,
+        INDEXED,
+        BitDepth::Eight,
+    );
+}
+
+/// Always picks the last candidate, counting the lines seen
+#[derive(Debug, Default)]
+struct LastFilter {
+    lines: AtomicUsize,
+}
+
+impl FilterStrategy for LastFilter {
+    fn choose(&self, candidates: &[&[u8]], context: &LineContext<'_>) -> usize {
+        assert!(candidates
+            .iter()
+            .all(|c| c.len() == context.prev_line.len() + 1));
+        self.lines.fetch_add(1, Ordering::Relaxed);
+        candidates.len() - 1
+    }
+}
+
+#[test]
+fn filter_custom_strategy() {
+    let input = PathBuf::from("tests/files/rgb_8_should_be_rgb_8.png");
+    let (output, mut opts) = get_opts(&input);
+    let strategy = Arc::new(LastFilter::default());
+    opts.filter.clear();
+    opts.filter_strategies.push(strategy.clone());
+
+    let png = PngData::new(&input, &opts).unwrap();
+    let filtered = png.raw.filter_image_strategy(strategy.as_ref(), false);
+    let lines = strategy.lines.load(Ordering::Relaxed);
+    assert_eq!(lines, png.raw.ihdr.height as usize);
+    let line_len = filtered.len() / lines;
+    assert!(filtered
+        .chunks(line_len)
+        .all(|line| line[0] == RowFilter::Paeth as u8));
+
+    oxipng::optimize(&InFile::Path(input), &output, &opts).unwrap();
+    let output = output.path().unwrap();
+    assert!(strategy.lines.load(Ordering::Relaxed) > lines);
+    PngData::new(output, &opts).unwrap();
+    remove_file(output).ok();
+}
+
+#[test]
+fn cost_model_match_across_lines() {
+    // Bytes without any repeated sequences, so the only matches are the ones set up below
+    let mut state = 1u32;
+    let mut random = |len: usize| -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    };
+    let first = random(100);
+    let second = random(100);
+    let committed: [&[u8]; 2] = [&first, &second];
+
+    // A match starting in the last two bytes of a line must cost the same as one of equal length
+    // and similar distance that is contained within a line
+    let spanning: Vec<_> = first[98..].iter().chain(&second[..50]).copied().collect();
+    let within = &second[..52];
+    assert_eq!(
+        estimate_line_cost(&committed, &spanning),
+        estimate_line_cost(&committed, within)
+    );
+}